@@ -0,0 +1,109 @@
+use crate::error::SegmentError;
+
+/// Which neighbouring pixels get an edge.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Connectivity {
+    /// Horizontal and vertical neighbours.
+    Four,
+    /// Horizontal, vertical and diagonal neighbours.
+    #[default]
+    Eight,
+    /// Every pixel within Euclidean distance `r`; `Radius(1)` is `Four`.
+    Radius(u32),
+    /// Arbitrary `(row, column)` offsets. An offset and its mirror image
+    /// describe the same edge, so only one of them is needed.
+    Custom(Vec<(i32, i32)>),
+}
+
+impl Connectivity {
+    /// Forward half of the stencil as `(row, column)` offsets, one per undirected edge.
+    pub fn offsets(&self) -> Vec<(i32, i32)> {
+        match self {
+            Connectivity::Four => vec![(1, 0), (0, 1)],
+            Connectivity::Eight => vec![(1, 0), (0, 1), (1, 1), (1, -1)],
+            Connectivity::Radius(r) => {
+                // Squares in i64 cannot overflow for any radius that fits in i32.
+                let r = i32::try_from(*r).unwrap_or(i32::MAX);
+                let mut offsets = Vec::new();
+                for dy in 0..=r {
+                    for dx in -r..=r {
+                        if is_forward((dy, dx)) && (dy as i64).pow(2) + (dx as i64).pow(2) <= (r as i64).pow(2) {
+                            offsets.push((dy, dx));
+                        }
+                    }
+                }
+                offsets
+            }
+            Connectivity::Custom(stencil) => {
+                let mut offsets: Vec<(i32, i32)> = Vec::new();
+                for &(dy, dx) in stencil {
+                    // An offset whose mirror does not fit in i32 reaches past any image; `check` rejects it.
+                    let offset = if is_forward((dy, dx)) {
+                        Some((dy, dx))
+                    } else {
+                        dy.checked_neg().zip(dx.checked_neg())
+                    };
+                    if let Some(offset) = offset.filter(|&offset| offset != (0, 0) && !offsets.contains(&offset)) {
+                        offsets.push(offset);
+                    }
+                }
+                offsets
+            }
+        }
+    }
+
+    /// Whether the stencil has any neighbour at all.
+    pub(crate) fn has_neighbours(&self) -> bool {
+        match self {
+            Connectivity::Four | Connectivity::Eight => true,
+            Connectivity::Radius(r) => *r > 0,
+            Connectivity::Custom(stencil) => stencil.iter().any(|&offset| offset != (0, 0)),
+        }
+    }
+
+    /// Reject stencils that reach further than a `width` x `height` image, before
+    /// `offsets` expands them: a radius must be shorter than the longer side, and
+    /// a custom offset shorter than the image in each direction.
+    pub(crate) fn check(&self, width: u32, height: u32) -> Result<(), SegmentError> {
+        match self {
+            Connectivity::Radius(r) if *r >= width.max(height) => {
+                Err(SegmentError::invalid("connectivity", format!("radius {} reaches past a {}x{} image", r, width, height)))
+            }
+            Connectivity::Custom(stencil) => {
+                match stencil.iter().find(|(dy, dx)| dy.unsigned_abs() >= height || dx.unsigned_abs() >= width) {
+                    Some((dy, dx)) => Err(SegmentError::invalid(
+                        "connectivity",
+                        format!("offset ({}, {}) reaches past a {}x{} image", dy, dx, width, height),
+                    )),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_forward((dy, dx): (i32, i32)) -> bool {
+    dy > 0 || (dy == 0 && dx > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extreme_offsets_are_rejected_not_panicking() {
+        assert_eq!(Connectivity::Custom(vec![(i32::MIN, 0), (-1, 0)]).offsets(), vec![(1, 0)]);
+        assert_eq!(Connectivity::Radius(2).offsets().len(), 6);
+
+        let stencils = [
+            Connectivity::Custom(vec![(i32::MIN, 0)]),
+            Connectivity::Custom(vec![(0, i32::MAX)]),
+            Connectivity::Radius(50_000),
+        ];
+        for connectivity in stencils {
+            assert!(matches!(connectivity.check(16, 16), Err(SegmentError::InvalidParameter { .. })));
+        }
+        assert!(Connectivity::Radius(15).check(16, 4).is_ok());
+    }
+}
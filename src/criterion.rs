@@ -0,0 +1,161 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// A component of the disjoint-set forest, as seen by a `MergeCriterion`.
+#[derive(Debug, Clone, Copy)]
+pub struct Component {
+    pub size: u32,
+    pub smallest_edge: f32,
+    /// Criterion-specific state, NaN until `init` has filled it in.
+    pub score: f32,
+}
+
+/// Decides whether two components joined by an edge get merged.
+///
+/// Both the serial and the parallel `DisjointSetUnion` consult the criterion on
+/// every union. Each component carries a single `score` that the criterion owns.
+pub trait MergeCriterion: Send + Sync {
+    /// Score of a component that has none yet, met through an edge of `weight`.
+    fn init(&self, component: &Component, weight: f32) -> f32;
+
+    /// Returns the score of the merged component, or `None` to keep them apart.
+    fn merge(&self, a: &Component, b: &Component, weight: f32) -> Option<f32>;
+}
+
+/// PHMSF credit rule: a component starts with credit
+/// `(smallest_edge - 2 * contrast) * sqrt(4π * size)` and every merge spends `weight` of it.
+#[derive(Debug, Clone, Copy)]
+pub struct Credit {
+    pub contrast: f32,
+}
+
+impl MergeCriterion for Credit {
+    fn init(&self, component: &Component, weight: f32) -> f32 {
+        let min_perimeter = (4.0 * std::f32::consts::PI * component.size as f32).sqrt();
+        let contrast = if component.smallest_edge.is_nan() {
+            weight
+        } else {
+            component.smallest_edge
+        } - 2f32 * self.contrast;
+
+        contrast * min_perimeter
+    }
+
+    fn merge(&self, a: &Component, b: &Component, weight: f32) -> Option<f32> {
+        let credit = a.score.min(b.score);
+        if credit > weight {
+            Some(credit - weight)
+        } else {
+            None
+        }
+    }
+}
+
+/// Merge whenever the edge is lighter than `threshold`.
+#[derive(Debug, Clone, Copy)]
+pub struct Threshold {
+    pub threshold: f32,
+}
+
+impl MergeCriterion for Threshold {
+    fn init(&self, _: &Component, _: f32) -> f32 {
+        f32::NAN
+    }
+
+    fn merge(&self, _: &Component, _: &Component, weight: f32) -> Option<f32> {
+        if weight < self.threshold {
+            Some(f32::NAN)
+        } else {
+            None
+        }
+    }
+}
+
+/// Felzenszwalb–Huttenlocher rule: merge when `weight <= min(Int(C) + k / |C|)`
+/// over both components, where the score holds the internal difference `Int(C)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Felzenszwalb {
+    pub k: f32,
+}
+
+impl MergeCriterion for Felzenszwalb {
+    fn init(&self, _: &Component, _: f32) -> f32 {
+        0f32
+    }
+
+    fn merge(&self, a: &Component, b: &Component, weight: f32) -> Option<f32> {
+        let tau = |c: &Component| c.score + self.k / c.size as f32;
+        if weight <= tau(a).min(tau(b)) {
+            Some(weight.max(a.score).max(b.score))
+        } else {
+            None
+        }
+    }
+}
+
+/// Merge whenever either component is smaller than `min_size` pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct MinSize {
+    pub min_size: u32,
+}
+
+impl MergeCriterion for MinSize {
+    fn init(&self, _: &Component, _: f32) -> f32 {
+        f32::NAN
+    }
+
+    fn merge(&self, a: &Component, b: &Component, weight: f32) -> Option<f32> {
+        if a.size < self.min_size || b.size < self.min_size {
+            Some(weight.max(a.score).max(b.score))
+        } else {
+            None
+        }
+    }
+}
+
+/// Merge unconditionally; joins the pixels of a scribble before any other phase.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MustLink;
+
+impl MergeCriterion for MustLink {
+    fn init(&self, _: &Component, _: f32) -> f32 {
+        f32::NAN
+    }
+
+    fn merge(&self, _: &Component, _: &Component, _: f32) -> Option<f32> {
+        Some(f32::NAN)
+    }
+}
+
+/// Merge criterion of the credit phase, selected in `Params`.
+#[derive(Clone, Default)]
+pub enum Criterion {
+    /// `Credit` with `Params::contrast`.
+    #[default]
+    Credit,
+    Threshold(f32),
+    Felzenszwalb { k: f32 },
+    Custom(Arc<dyn MergeCriterion>),
+}
+
+impl Criterion {
+    pub(crate) fn build(&self, contrast: f32) -> Arc<dyn MergeCriterion> {
+        match self {
+            Criterion::Credit => Arc::new(Credit { contrast }),
+            Criterion::Threshold(threshold) => Arc::new(Threshold { threshold: *threshold }),
+            Criterion::Felzenszwalb { k } => Arc::new(Felzenszwalb { k: *k }),
+            Criterion::Custom(criterion) => Arc::clone(criterion),
+        }
+    }
+}
+
+impl fmt::Debug for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Criterion::Credit => write!(f, "Credit"),
+            Criterion::Threshold(threshold) => write!(f, "Threshold({})", threshold),
+            Criterion::Felzenszwalb { k } => write!(f, "Felzenszwalb {{ k: {} }}", k),
+            Criterion::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
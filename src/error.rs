@@ -0,0 +1,72 @@
+use image::ImageError;
+use std::fmt;
+
+/// Everything that can go wrong while loading or segmenting an image.
+#[derive(Debug)]
+pub enum SegmentError {
+    /// The image file could not be read.
+    Io(std::io::Error),
+    /// The file was read but could not be decoded.
+    Decode(ImageError),
+    /// The image or buffer uses a pixel layout the loaders cannot handle.
+    UnsupportedFormat(String),
+    /// A parameter is out of range, e.g. a zero tile size or a NaN threshold.
+    InvalidParameter { name: &'static str, reason: String },
+    /// The image has more pixels than a `u32` node index can address.
+    TooLarge { width: u64, height: u64 },
+}
+
+impl SegmentError {
+    pub(crate) fn invalid(name: &'static str, reason: impl Into<String>) -> SegmentError {
+        SegmentError::InvalidParameter { name, reason: reason.into() }
+    }
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::Io(e) => write!(f, "i/o error: {}", e),
+            SegmentError::Decode(e) => write!(f, "failed to decode image: {}", e),
+            SegmentError::UnsupportedFormat(format) => write!(f, "unsupported pixel format: {}", format),
+            SegmentError::InvalidParameter { name, reason } => write!(f, "invalid {}: {}", name, reason),
+            SegmentError::TooLarge { width, height } => {
+                write!(f, "image of {}x{} pixels is too large to segment", width, height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SegmentError::Io(e) => Some(e),
+            SegmentError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SegmentError {
+    fn from(e: std::io::Error) -> Self {
+        SegmentError::Io(e)
+    }
+}
+
+impl From<ImageError> for SegmentError {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::IoError(e) => SegmentError::Io(e),
+            ImageError::Unsupported(e) => SegmentError::UnsupportedFormat(e.to_string()),
+            e => SegmentError::Decode(e),
+        }
+    }
+}
+
+/// Node indices are `u32`, so the pixel count has to fit in one.
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<(), SegmentError> {
+    let nodes = width as u64 * height as u64;
+    if nodes > u32::MAX as u64 {
+        return Err(SegmentError::TooLarge { width: width as u64, height: height as u64 });
+    }
+    Ok(())
+}
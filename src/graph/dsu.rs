@@ -1,30 +1,67 @@
+use std::collections::HashMap;
 use std::mem::swap;
+use std::sync::Arc;
+use crate::criterion::{Component, Credit, MergeCriterion, MustLink, Threshold};
 
+/// Seed label of a component that holds no scribbled pixel.
+pub(crate) const NO_SEED: u32 = u32::MAX;
+
+#[derive(Clone)]
 pub struct DisjointSetUnion {
     parent: Vec<usize>,
     smallest_edge: Vec<f32>,
     size: Vec<i32>,
     credit: Vec<f32>,
+    seed: Vec<u32>,
     pub threshold: f32,
-    contrast: f32,
+    criterion: Arc<dyn MergeCriterion>,
+    merges: Option<Vec<(usize, usize, f32)>>,
 }
 
 impl DisjointSetUnion {
-    pub(crate) fn new (nodes: usize, threshold: f32, contrast: f32) -> DisjointSetUnion {
-        let mut parent: Vec<usize> = vec![0; nodes ];
+    pub fn new (nodes: usize, threshold: f32, contrast: f32) -> DisjointSetUnion {
+        DisjointSetUnion::with_criterion(nodes, threshold, Arc::new(Credit { contrast }))
+    }
+
+    pub fn with_criterion (nodes: usize, threshold: f32, criterion: Arc<dyn MergeCriterion>) -> DisjointSetUnion {
+        let parent: Vec<usize> = (0..nodes).collect();
         let smallest_edge: Vec<f32> = vec![f32::NAN; nodes];
         let size: Vec<i32> = vec![1; nodes];
         let credit: Vec<f32> = vec![f32::NAN; nodes];
-        for i in 0..nodes {
-            parent[i]=i;
-        }
+        let seed: Vec<u32> = vec![NO_SEED; nodes];
         DisjointSetUnion {
             parent,
             smallest_edge,
             size,
             credit,
+            seed,
             threshold,
-            contrast,
+            criterion,
+            merges: None,
+        }
+    }
+
+    /// Log every successful union from now on, for building a merge tree.
+    pub fn record_merges (&mut self) {
+        self.merges.get_or_insert_with(Vec::new);
+    }
+
+    /// Unions logged so far as `(kept root, absorbed root, weight)`.
+    pub fn take_merges (&mut self) -> Vec<(usize, usize, f32)> {
+        self.merges.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Label the `(node, label)` seeds and join all nodes that share a label.
+    /// Unions between components with different labels are refused from now on.
+    pub fn constrain (&mut self, seeds: &[(usize, u32)]) {
+        let mut first = HashMap::new();
+        for &(node, label) in seeds {
+            let root = self.find(node);
+            if self.seed[root] == NO_SEED {
+                self.seed[root] = label;
+            }
+            let head = *first.entry(label).or_insert(node);
+            self.union_with(head, node, 0f32, &MustLink);
         }
     }
 
@@ -36,59 +73,62 @@ impl DisjointSetUnion {
         self.parent[node]
     }
 
-    pub fn credit (&mut self, node: usize, weight: f32) -> f32{
-        let par = self.find(node);
-        let mut min_perimeter: f32 = 4.0 * std::f32::consts::PI * self.size[par] as f32;
-        min_perimeter = min_perimeter.sqrt();
-        let mut contrast = 0f32;
-
-        if self.smallest_edge[node].is_nan() {
-            contrast = weight;
-        } else {
-            contrast = self.smallest_edge[node];
+    fn component (&self, root: usize) -> Component {
+        Component {
+            size: self.size[root] as u32,
+            smallest_edge: self.smallest_edge[root],
+            score: self.credit[root],
         }
+    }
+
+    pub fn credit (&mut self, node: usize, weight: f32) -> f32 {
+        let par = self.find(node);
+        self.criterion.init(&self.component(par), weight)
+    }
 
-        contrast = contrast - 2f32*(self.contrast);
+    /// Union under the configured merge criterion.
+    pub fn union (&mut self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)> {
+        let criterion = Arc::clone(&self.criterion);
+        self.union_with(u, v, weight, criterion.as_ref())
+    }
 
-        contrast*min_perimeter
+    pub fn union_threshold (&mut self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)> {
+        let criterion = Threshold { threshold: self.threshold };
+        self.union_with(u, v, weight, &criterion)
     }
 
-    pub fn union (&mut self, u: usize, v: usize, weight: f32) {
+    /// Returns `(root, absorbed root)` when the two components were merged.
+    pub fn union_with (&mut self, u: usize, v: usize, weight: f32, criterion: &dyn MergeCriterion) -> Option<(usize, usize)> {
         let mut u = self.find(u);
         let mut v = self.find(v);
-        if u != v {
+        if u == v {
+            return None;
+        }
+        if self.seed[u] != NO_SEED && self.seed[v] != NO_SEED && self.seed[u] != self.seed[v] {
+            return None;
+        }
 
-            if self.credit[u].is_nan() {
-                self.credit[u] = self.credit(u, weight);
-            }
-            if self.credit[v].is_nan() {
-                self.credit[v] = self.credit(v, weight);
-            }
-            let credit = self.credit[u].min(self.credit[v]);
-            if credit > weight {
-                if self.size[u] < self.size[v] {
-                    swap (&mut u, &mut v);
-                }
-                self.parent[v] = u;
-                self.size[u] += self.size[v];
-                self.smallest_edge[u] = self.smallest_edge[u].min(self.smallest_edge[v]);
-                self.credit[u] = credit-weight;
-            }
+        if self.credit[u].is_nan() {
+            self.credit[u] = criterion.init(&self.component(u), weight);
         }
-    }
+        if self.credit[v].is_nan() {
+            self.credit[v] = criterion.init(&self.component(v), weight);
+        }
+        let credit = criterion.merge(&self.component(u), &self.component(v), weight)?;
 
-    pub fn union_threshold (&mut self, u: usize, v: usize, weight: f32) {
-        let mut u = self.find(u);
-        let mut v = self.find(v);
-        if u != v {
-            if weight < self.threshold {
-                if self.size[u] < self.size[v] {
-                    swap (&mut u, &mut v);
-                }
-                self.parent[v] = u;
-                self.size[u] += self.size[v];
-                self.smallest_edge[u] = self.smallest_edge[u].min(self.smallest_edge[v]);
-            }
+        if self.size[u] < self.size[v] {
+            swap (&mut u, &mut v);
+        }
+        self.parent[v] = u;
+        self.size[u] += self.size[v];
+        self.smallest_edge[u] = self.smallest_edge[u].min(self.smallest_edge[v]);
+        self.credit[u] = credit;
+        if self.seed[u] == NO_SEED {
+            self.seed[u] = self.seed[v];
         }
+        if let Some(merges) = &mut self.merges {
+            merges.push((u, v, weight));
+        }
+        Some((u, v))
     }
-}
\ No newline at end of file
+}
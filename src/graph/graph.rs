@@ -1,27 +1,57 @@
-use image::Rgba;
+use crate::error::SegmentError;
+use crate::graph::image::graph_from_image;
+use crate::histogram::WeightHistogram;
+use crate::params::Params;
+use crate::sample::Sample;
+use crate::source::ImageSource;
+use image::{DynamicImage, RgbImage};
 
 pub struct Graph {
-    pub dimensions: (u32, u32),
-    pub pixel: Vec<Vec<(u8, u8, u8)>>,
+    pub dimensions: (u32, u32),             // (width, height)
+    pub pixels: Vec<f32>,                   // native samples, row-major, `channels` per pixel
+    pub channels: u32,
     pub nodes: u32,                         // number of nodes
     pub adj_list: Vec<Vec<(u32, f32)>>,     // (node, weight)
+    pub histogram: WeightHistogram,
 }
 impl Graph {
     pub fn new(nodes: u32, width: u32, height: u32) -> Graph {
         Graph {
-            dimensions: (height, width),
+            dimensions: (width, height),
             nodes,
             adj_list: vec![Vec::new(); nodes as usize],
-            pixel: vec![vec![(0, 0, 0); width as usize]; height as usize],
+            pixels: vec![0f32; nodes as usize * 3],
+            channels: 3,
+            histogram: WeightHistogram::default(),
         }
     }
 
+    pub fn from_image(image: &DynamicImage, params: &Params) -> Result<Graph, SegmentError> {
+        graph_from_image(image, params)
+    }
+
+    pub fn from_rgb_image(image: &RgbImage, params: &Params) -> Result<Graph, SegmentError> {
+        graph_from_image(image, params)
+    }
+
+    /// Build from rows of packed RGB pixels that start `stride` samples apart.
+    pub fn from_raw<S: Sample>(data: &[S], width: u32, height: u32, stride: usize, params: &Params) -> Result<Graph, SegmentError> {
+        graph_from_image(ImageSource::from_raw(data, width, height, stride)?, params)
+    }
+
     pub fn add_edge(&mut self, u: u32, v: u32, w: f32) {
         self.adj_list[u as usize].push((v, w));
         self.adj_list[v as usize].push((u, w));
+        self.histogram.add(w);
+    }
+
+    pub fn pixel (&self, x: u32, y: u32) -> &[f32] {
+        let i = (y * self.dimensions.0 + x) as usize * self.channels as usize;
+        &self.pixels[i..i + self.channels as usize]
     }
 
-    pub fn set_pixel (&mut self, pixel: Rgba<u8>, x: u32, y: u32) {
-        self.pixel[x as usize][y as usize] = (pixel[0], pixel[1], pixel[2]);
+    pub fn set_pixel (&mut self, pixel: &[f32], x: u32, y: u32) {
+        let i = (y * self.dimensions.0 + x) as usize * self.channels as usize;
+        self.pixels[i..i + self.channels as usize].copy_from_slice(pixel);
     }
-}
\ No newline at end of file
+}
@@ -1,33 +1,54 @@
-use image::GenericImageView;
 // Generate graph from an image
+use crate::error::{check_dimensions, SegmentError};
 use crate::graph::graph::{Graph};
+use crate::mask::effective_mask;
+use crate::metric::WeightMetric;
+use crate::params::Params;
+use crate::sample::Sample;
+use crate::source::ImageSource;
 
-pub fn load_graph_from_image(path: &str) -> Result<Graph, Box<dyn std::error::Error>> {
+pub fn load_graph_from_image(path: &str) -> Result<Graph, SegmentError> {
     let image = image::open(path)?;
+    graph_from_image(&image, &Params::default())
+}
 
+pub fn graph_from_image<'a, S: Sample>(image: impl Into<ImageSource<'a, S>>, params: &Params) -> Result<Graph, SegmentError> {
+    let image = image.into();
+    let metric = &params.metric;
     let (width, height) = image.dimensions();
+    check_dimensions(width, height)?;
+    params.connectivity.check(width, height)?;
+    let offsets = params.connectivity.offsets();
+    let mask = effective_mask(&image, params)?;
+    let mask = mask.as_deref();
+    let kept = |x: u32, y: u32| mask.is_none_or(|mask| mask.contains(x, y));
+
+    let (features, dims) = image.features(params)?;
+    let feature = |node: u32| &features[node as usize * dims..(node as usize + 1) * dims];
 
     let mut graph = Graph::new(width * height, width, height); // Ensure the graph is initialized with the correct size
+    graph.channels = image.channels() as u32;
+    graph.pixels = image.values();
 
-    for ux in 0..height {
-        for uy in 0..width {
-            let x = ux as i32;
-            let y = uy as i32;
-            let neighbours: Vec<(i32, i32)> = vec![(x + 1, y), (x, y + 1), (x + 1, y + 1), (x + 1, y - 1)];
+    for uy in 0..height {
+        for ux in 0..width {
+            let y = uy as i64;
+            let x = ux as i64;
+            let neighbours = offsets.iter().map(|&(dy, dx)| (y + dy as i64, x + dx as i64));
 
-            let pixel1 = image.get_pixel(ux, uy);
-            graph.set_pixel(pixel1, ux, uy);
+            if !kept(ux, uy) {
+                continue;
+            }
 
             for i in neighbours {
-                if (i.0 < height as i32 && i.0 >= 0) && (i.1 < width as i32 && i.1 >= 0) {
-                    let pixel2 = image.get_pixel(i.0 as u32, i.1 as u32);
-                    let mut weight: f32 = (pixel1[0] as f32 - pixel2[0] as f32).powf(2f32) + (pixel1[1] as f32 - pixel2[1] as f32).powf(2f32) + (pixel1[2] as f32 - pixel2[2] as f32).powf(2f32);
-                    weight = weight.sqrt();
-                    graph.add_edge(ux * width + uy, (i.0 as u32) * width + i.1 as u32, weight);
+                if (i.0 < height as i64 && i.0 >= 0) && (i.1 < width as i64 && i.1 >= 0) && kept(i.1 as u32, i.0 as u32) {
+                    let (node1, node2) = (uy * width + ux, (i.0 as u32) * width + i.1 as u32);
+                    let weight = metric.weight(feature(node1), feature(node2));
+                    graph.add_edge(node1, node2, weight);
                 }
             }
         }
     }
 
     Ok(graph)
-}
\ No newline at end of file
+}
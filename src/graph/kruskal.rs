@@ -1,15 +1,25 @@
 use std::cmp::max;
-use std::collections::HashMap;
+use std::mem::swap;
+use std::sync::Arc;
+use crate::criterion::{Credit, MergeCriterion, MinSize};
 use crate::graph::dsu::DisjointSetUnion;
 use crate::graph::graph::Graph;
+use crate::segmentation::{Phase, Segmentation, Snapshot};
+use crate::tree::MergeTree;
 
 pub struct Kruskal {
     edges: Vec<(f32, u32, u32)>,
     dsu: DisjointSetUnion,
+    dimensions: (usize, usize),
+    seeds: Vec<(usize, u32)>,
 }
 
 impl Kruskal {
     pub fn new (graph: &Graph, threshold: f32, contrast: f32) -> Kruskal {
+        Kruskal::with_criterion(graph, threshold, Arc::new(Credit { contrast }))
+    }
+
+    pub fn with_criterion (graph: &Graph, threshold: f32, criterion: Arc<dyn MergeCriterion>) -> Kruskal {
         let mut edges: Vec<(f32, u32, u32)> = Vec::new();
         let mut mxu=0;
         let mut mxv=0;
@@ -20,14 +30,48 @@ impl Kruskal {
                 edges.push((v.1, u, v.0));
             }
         }
-        edges.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0));
 
         Kruskal {
             edges,
-            dsu: DisjointSetUnion::new(graph.nodes as usize, threshold, contrast),
+            dsu: DisjointSetUnion::with_criterion(graph.nodes as usize, threshold, criterion),
+            dimensions: (graph.dimensions.0 as usize, graph.dimensions.1 as usize),
+            seeds: Vec::new(),
         }
     }
 
+    /// Keep each edge once, as `(weight, lower node, higher node)`, ordered by
+    /// that whole key. The parallel engine's deterministic mode merges in this
+    /// same order, so both engines then produce the same partition.
+    pub fn canonical_order (&mut self) {
+        for edge in &mut self.edges {
+            if edge.1 > edge.2 {
+                swap(&mut edge.1, &mut edge.2);
+            }
+        }
+        self.edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        self.edges.dedup();
+    }
+
+    /// Edges as `(weight, u, v)`, sorted by weight.
+    pub fn edges (&self) -> &[(f32, u32, u32)] {
+        &self.edges
+    }
+
+    /// Start over from singleton components, keeping the sorted edges.
+    pub fn reset (&mut self, threshold: f32, criterion: Arc<dyn MergeCriterion>) {
+        let (width, height) = self.dimensions;
+        self.dsu = DisjointSetUnion::with_criterion(width * height, threshold, criterion);
+        self.dsu.constrain(&self.seeds);
+    }
+
+    /// Join the `(node, label)` scribble seeds and keep different labels apart
+    /// in every later phase, including after a `reset`.
+    pub fn constrain (&mut self, seeds: &[(usize, u32)]) {
+        self.seeds = seeds.to_vec();
+        self.dsu.constrain(seeds);
+    }
+
     pub fn apply (&mut self, ind: usize) {
         for i in ind..self.edges.len() {
             let edge = self.edges[i];
@@ -36,7 +80,13 @@ impl Kruskal {
     }
 
     pub fn apply_threshold (&mut self) -> usize {
-        for (ind, edge) in self.edges.iter().enumerate() {
+        self.apply_threshold_from(0)
+    }
+
+    /// Threshold phase over the edges from `start` on. Returns the index of the
+    /// first edge heavier than the threshold, where the credit phase begins.
+    pub fn apply_threshold_from (&mut self, start: usize) -> usize {
+        for (ind, edge) in self.edges.iter().enumerate().skip(start) {
             if edge.0 > self.dsu.threshold {
                 return ind;
             }
@@ -45,40 +95,104 @@ impl Kruskal {
         self.edges.len()
     }
 
-    pub fn relabel (&mut self, image: &Vec<Vec<(u8, u8, u8)>>) -> Vec<Vec<(u8, u8, u8)>> {
-        let mut region_colors: HashMap<usize, (f32, f32, f32, i32)> = HashMap::new();
+    /// Record unions from now on so that `merge_tree` can return them.
+    pub fn record_merges (&mut self) {
+        self.dsu.record_merges();
+    }
+
+    pub fn merge_tree (&mut self) -> MergeTree {
+        let (width, height) = self.dimensions;
+        MergeTree::from_unions(width, height, &self.dsu.take_merges())
+    }
+
+    pub fn segmentation (&mut self) -> Segmentation {
+        let (width, height) = self.dimensions;
+        Segmentation::from_roots(width, height, |node| self.dsu.find(node))
+    }
 
-        let height = image.len();
-        let width = image[0].len();
+    /// Run the threshold phase followed by the credit phase, then absorb
+    /// components smaller than `min_region_size`.
+    pub fn run (&mut self, min_region_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
 
-        for y in 0..height {
-            for x in 0..width {
-                let region_id = self.dsu.find(y * width + x);
-                let (r, g, b) = image[y][x];
+        let ind = self.apply_threshold();
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::Threshold, segmentation: self.segmentation() });
+        }
+
+        self.apply(ind);
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::Credit, segmentation: self.segmentation() });
+        }
 
-                let entry = region_colors.entry(region_id).or_insert((0.0, 0.0, 0.0, 0));
-                entry.0 += r as f32;
-                entry.1 += g as f32;
-                entry.2 += b as f32;
-                entry.3 += 1;
+        if min_region_size > 1 {
+            self.merge_small(min_region_size);
+            if snapshots {
+                phases.push(Snapshot { phase: Phase::MinSize, segmentation: self.segmentation() });
             }
         }
 
-        let mut result_image = vec![vec![(0, 0, 0); width]; height];
+        let mut segmentation = self.segmentation();
+        segmentation.snapshots = phases;
+        segmentation
+    }
 
-        for y in 0..height {
-            for x in 0..width {
-                let region_id = self.dsu.find(y * width + x);
-                let (r_sum, g_sum, b_sum, count) = region_colors[&region_id];
+    /// One segmentation per threshold, in the order given, from a single sweep of
+    /// the threshold phase. Each threshold's credit phase runs on a copy of the
+    /// forest, so the sort and the threshold unions are shared by all of them.
+    pub fn run_multi (&mut self, thresholds: &[f32], min_region_size: u32) -> Vec<Segmentation> {
+        let mut order: Vec<usize> = (0..thresholds.len()).collect();
+        order.sort_by(|&a, &b| thresholds[a].total_cmp(&thresholds[b]));
 
-                let avg_r = (r_sum / count as f32) as u8;
-                let avg_g = (g_sum / count as f32) as u8;
-                let avg_b = (b_sum / count as f32) as u8;
+        let mut results = vec![None; thresholds.len()];
+        let mut next = 0;
+        for i in order {
+            let threshold = thresholds[i];
+            self.dsu.threshold = threshold;
+            let ind = self.apply_threshold_from(next);
+            // Edges equal to this threshold were not merged and stay pending for the next one.
+            next = self.edges.partition_point(|edge| edge.0 < threshold);
 
-                result_image[y][x] = (avg_r, avg_g, avg_b);
+            let forest = self.dsu.clone();
+            self.apply(ind);
+            if min_region_size > 1 {
+                self.merge_small(min_region_size);
             }
+            let mut segmentation = self.segmentation();
+            segmentation.threshold = threshold;
+            results[i] = Some(segmentation);
+            self.dsu = forest;
         }
 
-        result_image
+        results.into_iter().flatten().collect()
+    }
+
+    /// Merge every component smaller than `min_size` into the neighbour across
+    /// its lightest edge.
+    pub fn merge_small (&mut self, min_size: u32) {
+        let criterion = MinSize { min_size };
+        for edge in &self.edges {
+            self.dsu.union_with(edge.1 as usize, edge.2 as usize, edge.0, &criterion);
+        }
     }
-}
\ No newline at end of file
+
+    /// Felzenszwalb–Huttenlocher segmentation over the sorted edges. The DSU must
+    /// have been built with the `Felzenszwalb` criterion.
+    pub fn felzenszwalb (&mut self, min_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
+
+        self.apply(0);
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::Felzenszwalb, segmentation: self.segmentation() });
+        }
+
+        self.merge_small(min_size);
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::MinSize, segmentation: self.segmentation() });
+        }
+
+        let mut segmentation = self.segmentation();
+        segmentation.snapshots = phases;
+        segmentation
+    }
+}
@@ -1,4 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod graph;
-pub(crate) mod image;
+pub mod image;
 pub mod kruskal;
-pub mod dsu;
\ No newline at end of file
+pub mod dsu;
@@ -0,0 +1,178 @@
+const BINS: usize = 4096;
+
+/// Histogram of edge weights, filled in by the graph builders as edges are added.
+///
+/// Keeps a fixed number of bins and doubles the bin width whenever a weight falls
+/// past the last bin, so it adapts to any weight range without a first pass.
+#[derive(Debug, Clone)]
+pub struct WeightHistogram {
+    bin_width: f32,
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for WeightHistogram {
+    fn default() -> Self {
+        WeightHistogram {
+            bin_width: 1f32 / BINS as f32,
+            counts: vec![0; BINS],
+            total: 0,
+        }
+    }
+}
+
+impl WeightHistogram {
+    pub fn add(&mut self, weight: f32) {
+        if !weight.is_finite() {
+            return;
+        }
+        let weight = weight.max(0f32);
+        while weight >= self.bin_width * BINS as f32 {
+            self.widen();
+        }
+        self.counts[((weight / self.bin_width) as usize).min(BINS - 1)] += 1;
+        self.total += 1;
+    }
+
+    /// Fold another histogram, e.g. one built by a different tile, into this one.
+    pub fn merge(&mut self, other: &WeightHistogram) {
+        let mut other = other.clone();
+        while self.bin_width < other.bin_width {
+            self.widen();
+        }
+        while other.bin_width < self.bin_width {
+            other.widen();
+        }
+        for (count, extra) in self.counts.iter_mut().zip(&other.counts) {
+            *count += extra;
+        }
+        self.total += other.total;
+    }
+
+    fn widen(&mut self) {
+        for i in 0..BINS / 2 {
+            self.counts[i] = self.counts[2 * i] + self.counts[2 * i + 1];
+        }
+        self.counts[BINS / 2..].fill(0);
+        self.bin_width *= 2f32;
+    }
+
+    /// Number of edges seen.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn center(&self, bin: usize) -> f32 {
+        (bin as f32 + 0.5) * self.bin_width
+    }
+
+    /// Weight below which `percent` percent of the edges fall.
+    pub fn percentile(&self, percent: f32) -> f32 {
+        let rank = (self.total as f64 * (percent as f64 / 100.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bin, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self.center(bin);
+            }
+        }
+        self.center(BINS - 1)
+    }
+
+    pub fn median(&self) -> f32 {
+        self.percentile(50f32)
+    }
+
+    /// Median absolute deviation from the median.
+    pub fn mad(&self) -> f32 {
+        let median = self.median();
+        let mut deviations: Vec<(f32, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bin, &count)| ((self.center(bin) - median).abs(), count))
+            .collect();
+        deviations.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let half = self.total.div_ceil(2);
+        let mut seen = 0;
+        for (deviation, count) in deviations {
+            seen += count;
+            if seen >= half {
+                return deviation;
+            }
+        }
+        0f32
+    }
+
+    /// Otsu's threshold: the split that maximises the between-class variance.
+    pub fn otsu(&self) -> f32 {
+        let total = self.total as f64;
+        let sum: f64 = self.counts.iter().enumerate().map(|(bin, &count)| bin as f64 * count as f64).sum();
+
+        let (mut weight_low, mut sum_low) = (0f64, 0f64);
+        let (mut best_bin, mut best_variance) = (0, -1f64);
+        for (bin, &count) in self.counts.iter().enumerate() {
+            weight_low += count as f64;
+            sum_low += bin as f64 * count as f64;
+            let weight_high = total - weight_low;
+            if weight_low == 0.0 || weight_high == 0.0 {
+                continue;
+            }
+            let mean_low = sum_low / weight_low;
+            let mean_high = (sum - sum_low) / weight_high;
+            let variance = weight_low * weight_high * (mean_low - mean_high).powi(2);
+            if variance > best_variance {
+                best_bin = bin;
+                best_variance = variance;
+            }
+        }
+        // Edges of the low class must fall below the threshold.
+        (best_bin + 1) as f32 * self.bin_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(weights: impl IntoIterator<Item = f32>) -> WeightHistogram {
+        let mut histogram = WeightHistogram::default();
+        for weight in weights {
+            histogram.add(weight);
+        }
+        histogram
+    }
+
+    #[test]
+    fn merge_matches_a_single_histogram() {
+        let weights: Vec<f32> = (0..3000).map(|i| (i * 37 % 1001) as f32 * 0.13).chain([0.5, 900f32, 4000f32]).collect();
+        // Parts widen to different bin widths before they are merged.
+        let mut merged = histogram(weights[..10].iter().copied());
+        merged.merge(&histogram(weights[10..2000].iter().copied()));
+        merged.merge(&histogram(weights[2000..].iter().copied()));
+
+        let single = histogram(weights.iter().copied());
+        assert_eq!(merged.bin_width, single.bin_width);
+        assert_eq!(merged.counts, single.counts);
+        assert_eq!(merged.total(), weights.len() as u64);
+    }
+
+    #[test]
+    fn statistics_of_two_clusters() {
+        // 600 weights in [2, 2.5) and 400 in [40, 41).
+        let low = (0..600).map(|i| 2f32 + i as f32 / 1200f32);
+        let high = (0..400).map(|i| 40f32 + i as f32 / 400f32);
+        let histogram = histogram(low.chain(high));
+        let slack = histogram.bin_width;
+
+        let otsu = histogram.otsu();
+        assert!((2.5..=40f32).contains(&otsu), "otsu {}", otsu);
+        assert!((2f32 - slack..2.5 + slack).contains(&histogram.percentile(50f32)));
+        assert!((40f32 - slack..41f32 + slack).contains(&histogram.percentile(90f32)));
+        assert!((2f32 - slack..2f32 + slack).contains(&histogram.percentile(0f32)));
+        // Over half of the weights lie within the low cluster's width of the median.
+        assert!(histogram.mad() <= 0.5 + slack, "mad {}", histogram.mad());
+    }
+}
@@ -0,0 +1,276 @@
+pub mod connectivity;
+pub mod criterion;
+mod error;
+pub mod graph;
+mod histogram;
+mod mask;
+pub mod metric;
+pub mod parallel;
+mod params;
+pub mod render;
+mod sample;
+mod scribbles;
+mod segmentation;
+mod source;
+mod target;
+mod tree;
+
+pub use connectivity::Connectivity;
+pub use criterion::{Criterion, MergeCriterion};
+pub use error::SegmentError;
+pub use mask::Mask;
+pub use metric::{Metric, WeightMetric};
+pub use histogram::WeightHistogram;
+pub use params::{Algorithm, AlphaPolicy, AutoThreshold, Engine, Params, TargetRegions, ThresholdUnits};
+pub use sample::Sample;
+pub use scribbles::Scribbles;
+pub use segmentation::{Mismatch, Phase, Segmentation, Snapshot};
+pub use source::ImageSource;
+pub use tree::{Merge, MergeTree};
+
+use graph::image::graph_from_image;
+use graph::kruskal::Kruskal;
+use image::DynamicImage;
+use mask::effective_mask;
+use parallel::algo::Algo;
+use parallel::graph::graph_from_image_with_tiles;
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Open and decode an image file, sorting failures into `SegmentError` variants.
+pub fn open_image(path: impl AsRef<Path>) -> Result<DynamicImage, SegmentError> {
+    Ok(image::open(path)?)
+}
+
+/// Segment `image` with the engine selected in `params`.
+///
+/// Accepts a `&DynamicImage`, a `&RgbImage` or an `ImageSource` wrapping a raw buffer.
+/// Pass an `ImageSource<u16>` or `ImageSource<f32>` to keep the precision of
+/// deeper images; a plain `&DynamicImage` is read as 8-bit.
+pub fn segment<'a, S: Sample>(image: impl Into<ImageSource<'a, S>>, params: &Params) -> Result<Segmentation, SegmentError> {
+    params.validate()?;
+    let image = image.into();
+
+    let mut params = params.clone();
+    params.mask = effective_mask(&image, &params)?.map(Cow::into_owned);
+    if let Some(target) = params.target_regions {
+        let graph = graph_from_image(&image, &params)?;
+        let mut algo = Kruskal::with_criterion(&graph, params.threshold, params.merge_criterion());
+        if params.deterministic {
+            algo.canonical_order();
+        }
+        algo.constrain(&seeds(&image, &params)?);
+        (params.threshold, params.contrast) = target::search(&mut algo, &params, &target);
+    }
+
+    let mut segmentation = run(&image, &mut params)?;
+    if let Some(mask) = &params.mask {
+        segmentation.apply_mask(mask);
+    }
+    segmentation.compute_means(&image);
+    segmentation.threshold = params.threshold;
+    segmentation.contrast = params.contrast;
+    for snapshot in &mut segmentation.snapshots {
+        snapshot.segmentation.threshold = params.threshold;
+        snapshot.segmentation.contrast = params.contrast;
+    }
+    Ok(segmentation)
+}
+
+/// Segment `image` once per threshold in `thresholds`, returning the results in
+/// the same order.
+///
+/// The graph is built and sorted once and the threshold phase is shared, so this
+/// costs about one serial run plus one credit phase per threshold. It needs the
+/// serial engine and PHMSF; `params.threshold`, snapshots and merge trees are ignored.
+pub fn segment_multi<'a, S: Sample>(
+    image: impl Into<ImageSource<'a, S>>,
+    params: &Params,
+    thresholds: &[f32],
+) -> Result<Vec<Segmentation>, SegmentError> {
+    params.validate()?;
+    if params.engine != Engine::Serial {
+        return Err(SegmentError::invalid("engine", "multi-threshold runs need the serial engine"));
+    }
+    if params.algorithm != Algorithm::Phmsf {
+        return Err(SegmentError::invalid("algorithm", "multi-threshold runs need phmsf"));
+    }
+    if params.auto_threshold.is_some() || params.target_regions.is_some() {
+        return Err(SegmentError::invalid("thresholds", "cannot be combined with automatic threshold selection"));
+    }
+    if thresholds.iter().any(|threshold| threshold.is_nan()) {
+        return Err(SegmentError::invalid("threshold", "must not be NaN"));
+    }
+
+    let image = image.into();
+    let mask = effective_mask(&image, params)?;
+    let graph = graph_from_image(&image, params)?;
+    let mut algo = Kruskal::with_criterion(&graph, params.threshold, params.merge_criterion());
+    if params.deterministic {
+        algo.canonical_order();
+    }
+    algo.constrain(&seeds(&image, params)?);
+    let mut segmentations = algo.run_multi(thresholds, params.min_region_size);
+    for segmentation in &mut segmentations {
+        if let Some(mask) = &mask {
+            segmentation.apply_mask(mask);
+        }
+        segmentation.compute_means(&image);
+        segmentation.contrast = params.contrast;
+    }
+    Ok(segmentations)
+}
+
+/// Segment `image` with both engines in deterministic mode and compare the
+/// partitions. Returns `None` when they agree, which they should; a `Mismatch`
+/// points at an engine bug.
+pub fn check_engines<'a, S: Sample>(
+    image: impl Into<ImageSource<'a, S>>,
+    params: &Params,
+) -> Result<Option<Mismatch>, SegmentError> {
+    let image = image.into();
+    let mut params = params.clone();
+    params.deterministic = true;
+    params.engine = Engine::Serial;
+    let serial = segment(&image, &params)?;
+    params.engine = Engine::Parallel;
+    let parallel = segment(&image, &params)?;
+    Ok(serial.compare(&parallel))
+}
+
+/// Scribble seeds of `params` as `(node, label)`, empty when there are none.
+fn seeds<S: Sample>(image: &ImageSource<S>, params: &Params) -> Result<Vec<(usize, u32)>, SegmentError> {
+    let (width, height) = image.dimensions();
+    match &params.scribbles {
+        Some(scribbles) => scribbles.nodes(width, height),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Runs the selected engine, storing the threshold it settled on back in `params`.
+fn run<S: Sample>(image: &ImageSource<S>, params: &mut Params) -> Result<Segmentation, SegmentError> {
+    let segmentation = match params.engine {
+        Engine::Serial => {
+            let graph = graph_from_image(image, params)?;
+            if let Some(auto) = params.auto_threshold {
+                params.threshold = auto.select(&graph.histogram);
+            }
+            let mut algo = Kruskal::with_criterion(&graph, params.threshold, params.merge_criterion());
+            if params.deterministic {
+                algo.canonical_order();
+            }
+            if params.merge_tree {
+                algo.record_merges();
+            }
+            algo.constrain(&seeds(image, params)?);
+            let mut segmentation = match params.algorithm {
+                Algorithm::Phmsf => algo.run(params.min_region_size, params.snapshots),
+                Algorithm::Felzenszwalb { min_size, .. } => {
+                    algo.felzenszwalb(min_size.max(params.min_region_size), params.snapshots)
+                }
+            };
+            if params.merge_tree {
+                segmentation.merge_tree = Some(algo.merge_tree());
+            }
+            segmentation
+        }
+        Engine::Parallel => {
+            let mut graph = graph_from_image_with_tiles(image, params)?;
+            params.threshold = graph.dsu.threshold;
+            let algo = Algo::new();
+            if params.merge_tree {
+                graph.dsu.record_merges();
+            }
+            graph.constrain(&seeds(image, params)?);
+            let mut segmentation = match (params.algorithm, params.deterministic) {
+                (Algorithm::Phmsf, false) => algo.apply(&mut graph, params.min_region_size, params.snapshots),
+                (Algorithm::Phmsf, true) => {
+                    algo.apply_deterministic(&mut graph, params.min_region_size, params.snapshots)
+                }
+                (Algorithm::Felzenszwalb { min_size, .. }, false) => {
+                    algo.felzenszwalb(&mut graph, min_size.max(params.min_region_size), params.snapshots)
+                }
+                (Algorithm::Felzenszwalb { min_size, .. }, true) => {
+                    algo.felzenszwalb_deterministic(&mut graph, min_size.max(params.min_region_size), params.snapshots)
+                }
+            };
+            if params.merge_tree {
+                segmentation.merge_tree = Some(algo.merge_tree(&graph));
+            }
+            segmentation
+        }
+    };
+    Ok(segmentation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // Smooth ramps with some texture; 50x37 does not divide into 16x16 tiles.
+    fn image() -> RgbImage {
+        RgbImage::from_fn(50, 37, |x, y| Rgb([(x * 5) as u8, (y * 7) as u8, ((x * y) % 97) as u8]))
+    }
+
+    #[test]
+    fn engines_agree_in_deterministic_mode() {
+        let image = image();
+        let params = Params { tile_width: 16, tile_height: 16, min_region_size: 4, ..Params::default() };
+        assert_eq!(check_engines(&image, &params).unwrap(), None);
+    }
+
+    #[test]
+    fn credit_phase_runs_without_border_edges() {
+        // A single tile has no border edges, which used to end the credit merge at once.
+        let params = Params { engine: Engine::Parallel, tile_width: 64, tile_height: 64, snapshots: true, ..Params::default() };
+        let segmentation = segment(&image(), &params).unwrap();
+        let count = |phase: Phase| {
+            let snapshot = segmentation.snapshots.iter().find(|snapshot| snapshot.phase == phase).unwrap();
+            snapshot.segmentation.region_count
+        };
+        assert!(count(Phase::Heuristic) < count(Phase::BorderMerge));
+    }
+
+    #[test]
+    fn multi_threshold_matches_single_runs() {
+        let image = image();
+        let params = Params { engine: Engine::Serial, min_region_size: 4, ..Params::default() };
+        let thresholds = [20f32, 5f32, 13f32, 13f32];
+        let segmentations = segment_multi(&image, &params, &thresholds).unwrap();
+        for (segmentation, &threshold) in segmentations.iter().zip(&thresholds) {
+            let single = segment(&image, &Params { threshold, ..params.clone() }).unwrap();
+            assert_eq!(segmentation.labels, single.labels, "threshold {}", threshold);
+            assert_eq!(segmentation.threshold, threshold);
+        }
+
+        let parallel = Params { engine: Engine::Parallel, ..params };
+        assert!(segment_multi(&image, &parallel, &thresholds).is_err());
+    }
+
+    #[test]
+    fn scribbles_link_and_separate() {
+        let image = image();
+        let mut scribbles = Scribbles::new();
+        scribbles.add(1, [(0, 0), (49, 36)]);
+        scribbles.add(2, [(25, 18), (0, 36)]);
+        for engine in [Engine::Serial, Engine::Parallel] {
+            for deterministic in [false, true] {
+                // High enough to merge the whole image without the scribbles.
+                let params = Params {
+                    engine,
+                    deterministic,
+                    threshold: 1000f32,
+                    tile_width: 16,
+                    tile_height: 16,
+                    scribbles: Some(scribbles.clone()),
+                    ..Params::default()
+                };
+                let segmentation = segment(&image, &params).unwrap();
+                assert_eq!(segmentation.label(0, 0), segmentation.label(49, 36));
+                assert_eq!(segmentation.label(25, 18), segmentation.label(0, 36));
+                assert_ne!(segmentation.label(0, 0), segmentation.label(25, 18), "{:?} {}", engine, deterministic);
+            }
+        }
+    }
+}
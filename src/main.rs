@@ -1,67 +1,428 @@
-mod graph;
-mod parallel;
-
-use image::{Rgb, RgbImage};
-use graph::image::load_graph_from_image;
-use crate::graph::kruskal::Kruskal;
-use crate::parallel::algo::Algo;
+use image::{ColorType, DynamicImage};
+use project::{
+    open_image, render, segment, segment_multi, Algorithm, AlphaPolicy, AutoThreshold, Connectivity, Engine, ImageSource,
+    Mask, Metric, Params, Scribbles, SegmentError, Segmentation, TargetRegions, ThresholdUnits,
+};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::time::Instant;
 
-fn main() {
-    let image_path = "static/4.1.05.tiff";
-    let threshold = 13f32;
-    let contrast = -3f32;
+const USAGE: &str = "\
+Usage: project [OPTIONS] <INPUT>
+
+Options:
+  -o, --output <PATH>        Output file [default: segmented_image.png]
+      --threshold <FLOAT>    Unconditional merge threshold [default: 13]
+      --normalized           Read --threshold, --contrast and --k in units where
+                             every channel spans 0..1, whatever the bit depth
+      --thresholds <LIST>    Comma-separated thresholds to segment with in one
+                             serial pass; writes one output per threshold
+      --auto-threshold <M>   Derive the threshold from the edge weights: otsu,
+                             percentile:P or mad:F; --threshold overrides it
+      --contrast <FLOAT>     Contrast of the credit merge phase [default: -3]
+      --algorithm <ALGO>     phmsf or fh (Felzenszwalb-Huttenlocher) [default: phmsf]
+      --k <FLOAT>            Scale parameter of fh [default: 300]
+      --min-size <N>         Smallest component kept by fh [default: 20]
+      --regions <N>          Search threshold for about N regions
+      --search-contrast      With --regions, also search the contrast
+      --min-region-size <N>  Merge smaller regions into their closest neighbour
+                             [default: 0, disabled]
+      --tile-size <N|WxH>    Tile size of the parallel engine [default: 64]
+      --metric <METRIC>      Edge weight: rgb-l2, rgb-l1, rgb-linf, lab76,
+                             lab2000 or intensity [default: rgb-l2]
+      --band-weights <LIST>  Comma-separated factor per channel applied before
+                             the metric, e.g. 1,1,2 to stress blue
+      --connectivity <C>     4, 8 or rN for every pixel within radius N
+                             [default: 8]
+      --engine <ENGINE>      serial, parallel or both [default: both]
+      --threads <N>          Number of worker threads [default: all cores]
+      --deterministic        Same partition on every run and thread count, and
+                             from both engines; slower parallel credit phase
+      --check                Run both engines with --deterministic and fail if
+                             their partitions differ
+      --mode <MODE>          mean (mean colours), random (colour per region)
+                             or labels (text label map) [default: mean]
+      --mask <PATH>          Only segment pixels that are non-zero in this image;
+                             the rest are left out (black, or label 4294967295)
+      --alpha <POLICY>       ignore, mask (leave transparent pixels out) or
+                             channel:W (alpha as a 4th channel of weight W)
+                             [default: ignore]
+      --scribbles <PATH>     Label image of user scribbles: pixels with the same
+                             non-zero value stay together, different values apart
+      --merge-tree <PATH>    Also write the merge tree of the run to PATH
+  -h, --help                 Print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EngineChoice {
+    Serial,
+    Parallel,
+    Both,
+}
 
-    let start_time = Instant::now();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    Mean,
+    Random,
+    Labels,
+}
 
-    match load_graph_from_image(image_path) {
-        Ok(graph) => {
-            let mut algo = Kruskal::new(&graph, threshold, contrast);
-            let ind = algo.apply_threshold();
+struct Cli {
+    input: PathBuf,
+    output: PathBuf,
+    engine: EngineChoice,
+    threads: Option<usize>,
+    mode: OutputMode,
+    merge_tree: Option<PathBuf>,
+    mask: Option<PathBuf>,
+    scribbles: Option<PathBuf>,
+    thresholds: Option<Vec<f32>>,
+    check: bool,
+    params: Params,
+}
 
-            let segmented_image1 = algo.relabel(&graph.pixel);
-            algo.apply(ind);
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_tile_size(value: &str) -> Result<(usize, usize), String> {
+    match value.split_once(['x', 'X']) {
+        Some((w, h)) => Ok((parse_value("--tile-size", w)?, parse_value("--tile-size", h)?)),
+        None => {
+            let n = parse_value("--tile-size", value)?;
+            Ok((n, n))
+        }
+    }
+}
 
-            let mut img1 = RgbImage::new(graph.dimensions.0, graph.dimensions.1);
+/// Returns `Ok(None)` when help was requested.
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Cli>, String> {
+    let mut input = None;
+    let mut output = PathBuf::from("segmented_image.png");
+    let mut engine = None;
+    let mut threads = None;
+    let mut merge_tree = None;
+    let mut mask = None;
+    let mut scribbles = None;
+    let mut thresholds = None;
+    let mut check = false;
+    let mut mode = OutputMode::Mean;
+    let mut params = Params::default();
+    let mut fh = false;
+    let mut k = 300f32;
+    let mut min_size = 20;
+    let mut regions = None;
+    let mut search_contrast = false;
+    let mut threshold_given = false;
+    let mut auto_threshold = None;
 
-            for y in 0..graph.dimensions.1 {
-                for x in 0..graph.dimensions.0 {
-                    let (r, g, b) = segmented_image1[y as usize][x as usize];
-                    img1.put_pixel(y, x, Rgb([255 - r, 255 - g, 255 - b]));
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = PathBuf::from(value()?),
+            "--threshold" => {
+                params.threshold = parse_value("--threshold", &value()?)?;
+                threshold_given = true;
+            }
+            "--band-weights" => {
+                let list: Result<Vec<f32>, String> =
+                    value()?.split(',').map(|w| parse_value("--band-weights", w.trim())).collect();
+                params.band_weights = Some(list?);
+            }
+            "--normalized" => params.threshold_units = ThresholdUnits::Normalized,
+            "--thresholds" => {
+                let list: Result<Vec<f32>, String> =
+                    value()?.split(',').map(|t| parse_value("--thresholds", t.trim())).collect();
+                thresholds = Some(list?);
+            }
+            "--auto-threshold" => {
+                let value = value()?;
+                auto_threshold = Some(match value.split_once(':') {
+                    None if value == "otsu" => AutoThreshold::Otsu,
+                    Some(("percentile", p)) => AutoThreshold::Percentile(parse_value("--auto-threshold", p)?),
+                    Some(("mad", factor)) => AutoThreshold::Mad { factor: parse_value("--auto-threshold", factor)? },
+                    _ => return Err(format!("unknown threshold method '{}'", value)),
+                });
+            }
+            "--contrast" => params.contrast = parse_value("--contrast", &value()?)?,
+            "--algorithm" => {
+                fh = match value()?.as_str() {
+                    "phmsf" => false,
+                    "fh" => true,
+                    other => return Err(format!("unknown algorithm '{}'", other)),
                 }
             }
-            img1.save("segmented_image_serial.png").expect("Failed to save the image");
-            let elapsed_time = start_time.elapsed();
-            println!("Time taken for sequential algorithm: {:?}", elapsed_time);
-            println!("Sequential algorithm applied successfully.");
-        },
-        Err(e) => {
-            eprintln!("Failed to load graph from image: {}", e);
+            "--k" => k = parse_value("--k", &value()?)?,
+            "--min-size" => min_size = parse_value("--min-size", &value()?)?,
+            "--regions" => regions = Some(parse_value("--regions", &value()?)?),
+            "--search-contrast" => search_contrast = true,
+            "--min-region-size" => params.min_region_size = parse_value("--min-region-size", &value()?)?,
+            "--tile-size" => (params.tile_width, params.tile_height) = parse_tile_size(&value()?)?,
+            "--metric" => {
+                params.metric = match value()?.as_str() {
+                    "rgb-l2" => Metric::RgbL2,
+                    "rgb-l1" => Metric::RgbL1,
+                    "rgb-linf" => Metric::RgbLInf,
+                    "lab76" => Metric::DeltaE76,
+                    "lab2000" => Metric::DeltaE2000,
+                    "intensity" => Metric::Intensity,
+                    other => return Err(format!("unknown metric '{}'", other)),
+                }
+            }
+            "--connectivity" => {
+                params.connectivity = match value()?.as_str() {
+                    "4" => Connectivity::Four,
+                    "8" => Connectivity::Eight,
+                    other => match other.strip_prefix('r') {
+                        Some(radius) => Connectivity::Radius(parse_value("--connectivity", radius)?),
+                        None => return Err(format!("unknown connectivity '{}'", other)),
+                    },
+                }
+            }
+            "--engine" => {
+                engine = Some(match value()?.as_str() {
+                    "serial" => EngineChoice::Serial,
+                    "parallel" => EngineChoice::Parallel,
+                    "both" => EngineChoice::Both,
+                    other => return Err(format!("unknown engine '{}'", other)),
+                })
+            }
+            "--threads" => {
+                let n: usize = parse_value("--threads", &value()?)?;
+                if n == 0 {
+                    return Err("--threads must be greater than zero".to_string());
+                }
+                threads = Some(n);
+            }
+            "--alpha" => {
+                let value = value()?;
+                params.alpha = match value.split_once(':') {
+                    None if value == "ignore" => AlphaPolicy::Ignore,
+                    None if value == "mask" => AlphaPolicy::MaskTransparent,
+                    Some(("channel", weight)) => AlphaPolicy::Channel(parse_value("--alpha", weight)?),
+                    _ => return Err(format!("unknown alpha policy '{}'", value)),
+                };
+            }
+            "--deterministic" => params.deterministic = true,
+            "--check" => check = true,
+            "--mask" => mask = Some(PathBuf::from(value()?)),
+            "--scribbles" => scribbles = Some(PathBuf::from(value()?)),
+            "--merge-tree" => {
+                merge_tree = Some(PathBuf::from(value()?));
+                params.merge_tree = true;
+            }
+            "--mode" => {
+                mode = match value()?.as_str() {
+                    "mean" => OutputMode::Mean,
+                    "random" => OutputMode::Random,
+                    "labels" => OutputMode::Labels,
+                    other => return Err(format!("unknown output mode '{}'", other)),
+                }
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => return Err(format!("unknown option '{}'", flag)),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
         }
     }
 
-    let start_time_parallel = Instant::now();
+    let input = input.ok_or("missing <INPUT>")?;
+    if check {
+        if thresholds.is_some() {
+            return Err("--check cannot be combined with --thresholds".to_string());
+        }
+        engine = Some(EngineChoice::Both);
+        params.deterministic = true;
+    }
+    if thresholds.is_some() {
+        if engine.is_some_and(|engine| engine != EngineChoice::Serial) {
+            return Err("--thresholds only runs on the serial engine".to_string());
+        }
+        params.engine = Engine::Serial;
+    }
+    let engine = engine.unwrap_or(EngineChoice::Both);
+    if fh {
+        params.algorithm = Algorithm::Felzenszwalb { k, min_size };
+    }
+    if !threshold_given {
+        params.auto_threshold = auto_threshold;
+    }
+    if let Some(count) = regions {
+        params.target_regions = Some(TargetRegions { count, search_contrast });
+    }
+    params.validate().map_err(|e| e.to_string())?;
+    Ok(Some(Cli { input, output, engine, threads, mode, merge_tree, mask, scribbles, thresholds, check, params }))
+}
 
-    match parallel::graph::load_graph_from_image_with_tiles(image_path, 64, 64, threshold, contrast) {
-        Ok(mut graph) => {
-            let algo: Algo = Algo::new();
-            let segmented_image = algo.apply(&mut graph);
-            let elapsed_time_parallel = start_time_parallel.elapsed();
+/// `out.png` becomes `out_serial.png` when both engines write their own file.
+fn output_path(output: &Path, suffix: Option<&str>) -> PathBuf {
+    match suffix {
+        None => output.to_path_buf(),
+        Some(suffix) => {
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+            let mut name = format!("{}_{}", stem, suffix);
+            if let Some(extension) = output.extension() {
+                name = format!("{}.{}", name, extension.to_string_lossy());
+            }
+            output.with_file_name(name)
+        }
+    }
+}
 
-            let mut img = RgbImage::new(graph.width as u32, graph.height as u32);
-            for y in 0..graph.height {
-                for x in 0..graph.width {
-                    let (r, g, b) = segmented_image[y][x];
-                    img.put_pixel(x as u32, y as u32, Rgb([255 - r, 255 - g, 255 - b]));
-                }
+fn write_labels(segmentation: &Segmentation, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "{} {} {}", segmentation.width, segmentation.height, segmentation.region_count)?;
+    for row in segmentation.labels.chunks(segmentation.width) {
+        let line: Vec<String> = row.iter().map(|label| label.to_string()).collect();
+        writeln!(file, "{}", line.join(" "))?;
+    }
+    file.flush()
+}
+
+fn save(segmentation: &Segmentation, image: &DynamicImage, mode: OutputMode, path: &Path) -> Result<(), String> {
+    let result = match mode {
+        OutputMode::Mean => render::mean_colors(segmentation, image).save(path).map_err(|e| e.to_string()),
+        OutputMode::Random => render::label_colors(segmentation).save(path).map_err(|e| e.to_string()),
+        OutputMode::Labels => write_labels(segmentation, path).map_err(|e| e.to_string()),
+    };
+    result.map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+/// Segment at the image's own bit depth, so 16-bit and float inputs keep their precision.
+fn segment_native(image: &DynamicImage, params: &Params) -> Result<Segmentation, SegmentError> {
+    match image.color() {
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+            segment(ImageSource::<u16>::from_dynamic_image(image), params)
+        }
+        ColorType::Rgb32F | ColorType::Rgba32F => segment(ImageSource::<f32>::from_dynamic_image(image), params),
+        _ => segment(image, params),
+    }
+}
+
+fn segment_multi_native(image: &DynamicImage, params: &Params, thresholds: &[f32]) -> Result<Vec<Segmentation>, SegmentError> {
+    match image.color() {
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+            segment_multi(ImageSource::<u16>::from_dynamic_image(image), params, thresholds)
+        }
+        ColorType::Rgb32F | ColorType::Rgba32F => {
+            segment_multi(ImageSource::<f32>::from_dynamic_image(image), params, thresholds)
+        }
+        _ => segment_multi(image, params, thresholds),
+    }
+}
+
+fn run(mut cli: Cli) -> Result<(), String> {
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|e| format!("failed to start thread pool: {}", e))?;
+    }
+
+    let image = open_image(&cli.input).map_err(|e| format!("failed to load {}: {}", cli.input.display(), e))?;
+    if let Some(path) = &cli.mask {
+        let mask = open_image(path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+        cli.params.mask = Some(Mask::from_image(&mask));
+    }
+    if let Some(path) = &cli.scribbles {
+        let scribbles = open_image(path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+        cli.params.scribbles = Some(Scribbles::from_image(&scribbles));
+    }
+
+    if let Some(thresholds) = &cli.thresholds {
+        let start_time = Instant::now();
+        let segmentations = segment_multi_native(&image, &cli.params, thresholds).map_err(|e| e.to_string())?;
+        println!("Time taken for {} thresholds: {:?}", thresholds.len(), start_time.elapsed());
+
+        for segmentation in &segmentations {
+            let suffix = format!("t{}", segmentation.threshold);
+            save(segmentation, &image, cli.mode, &output_path(&cli.output, Some(&suffix)))?;
+            println!("Threshold {}: {} regions", segmentation.threshold, segmentation.region_count);
+        }
+        return Ok(());
+    }
+
+    let runs: &[(Engine, &str)] = match cli.engine {
+        EngineChoice::Serial => &[(Engine::Serial, "Sequential")],
+        EngineChoice::Parallel => &[(Engine::Parallel, "Parallel")],
+        EngineChoice::Both => &[(Engine::Serial, "Sequential"), (Engine::Parallel, "Parallel")],
+    };
+
+    let mut results = Vec::new();
+    for &(engine, name) in runs {
+        let params = Params { engine, ..cli.params.clone() };
+
+        let start_time = Instant::now();
+        let segmentation = segment_native(&image, &params).map_err(|e| e.to_string())?;
+        let elapsed_time = start_time.elapsed();
+
+        let suffix = match (cli.engine, engine) {
+            (EngineChoice::Both, Engine::Serial) => Some("serial"),
+            (EngineChoice::Both, Engine::Parallel) => Some("parallel"),
+            _ => None,
+        };
+        save(&segmentation, &image, cli.mode, &output_path(&cli.output, suffix))?;
+        if let (Some(path), Some(tree)) = (&cli.merge_tree, &segmentation.merge_tree) {
+            let path = output_path(path, suffix);
+            File::create(&path)
+                .and_then(|file| tree.write_to(BufWriter::new(file)))
+                .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        }
+
+        println!("Time taken for {} algorithm: {:?}", name.to_lowercase(), elapsed_time);
+        println!("{} regions", segmentation.region_count);
+        if cli.params.target_regions.is_some() || cli.params.auto_threshold.is_some() {
+            println!("Selected threshold {} and contrast {}", segmentation.threshold, segmentation.contrast);
+        }
+        println!("{} algorithm applied successfully.", name);
+        results.push(segmentation);
+    }
+
+    if cli.check {
+        match results[0].compare(&results[1]) {
+            None => println!("Check passed: both engines produced the same partition."),
+            Some(mismatch) => {
+                return Err(format!(
+                    "check failed: {} pixels differ between the engines, first at ({}, {}); {} serial regions, {} parallel",
+                    mismatch.pixels, mismatch.first.0, mismatch.first.1, mismatch.regions.0, mismatch.regions.1
+                ));
             }
-            img.save("segmented_image_parallel.png").expect("Failed to save the image");
-            println!("Time taken for parallel algorithm: {:?}", elapsed_time_parallel);
-            println!("Parallel algorithm applied successfully.");
-        },
-        Err(e) => {
-            eprintln!("Failed to load graph from image with tiles: {}", e);
         }
     }
 
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,76 @@
+use crate::error::SegmentError;
+use crate::params::{AlphaPolicy, Params};
+use crate::sample::Sample;
+use crate::source::ImageSource;
+use image::DynamicImage;
+use std::borrow::Cow;
+
+/// Region of interest: pixels outside it get no edges and the
+/// `Segmentation::IGNORED` label.
+#[derive(Debug, Clone)]
+pub struct Mask {
+    width: u32,
+    height: u32,
+    keep: Vec<bool>,
+}
+
+impl Mask {
+    /// Every pixel with a non-zero luma value is kept.
+    pub fn from_image(image: &DynamicImage) -> Mask {
+        let luma = image.to_luma8();
+        let (width, height) = luma.dimensions();
+        Mask { width, height, keep: luma.pixels().map(|pixel| pixel.0[0] != 0).collect() }
+    }
+
+    /// One byte per pixel, row-major; every non-zero byte is kept.
+    pub fn from_buffer(width: u32, height: u32, data: &[u8]) -> Result<Mask, SegmentError> {
+        if data.len() != width as usize * height as usize {
+            return Err(SegmentError::invalid("mask", format!("expected {} bytes, got {}", width as usize * height as usize, data.len())));
+        }
+        Ok(Mask { width, height, keep: data.iter().map(|&byte| byte != 0).collect() })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.keep[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Number of pixels outside the region of interest.
+    pub fn ignored_count(&self) -> usize {
+        self.keep.iter().filter(|&&keep| !keep).count()
+    }
+
+    pub(crate) fn keeps(&self, node: usize) -> bool {
+        self.keep[node]
+    }
+
+    pub(crate) fn check(&self, width: u32, height: u32) -> Result<(), SegmentError> {
+        if (self.width, self.height) != (width, height) {
+            return Err(SegmentError::invalid("mask", format!(
+                "is {}x{} but the image is {}x{}", self.width, self.height, width, height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `params.mask`, narrowed to the opaque pixels of `image` under
+/// `AlphaPolicy::MaskTransparent`. `None` when every pixel is kept.
+pub(crate) fn effective_mask<'p, S: Sample>(image: &ImageSource<S>, params: &'p Params) -> Result<Option<Cow<'p, Mask>>, SegmentError> {
+    let (width, height) = image.dimensions();
+    if let Some(mask) = &params.mask {
+        mask.check(width, height)?;
+    }
+    if params.alpha != AlphaPolicy::MaskTransparent || !image.has_alpha() {
+        return Ok(params.mask.as_ref().map(Cow::Borrowed));
+    }
+
+    let keep = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| image.alpha(x, y).to_f32() != 0f32 && params.mask.as_ref().is_none_or(|mask| mask.contains(x, y)))
+        .collect();
+    Ok(Some(Cow::Owned(Mask { width, height, keep })))
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Distance between two neighbouring pixels, used as the edge weight.
+///
+/// Pixels are passed as channel values in the source's native range (0..=255 for
+/// RGB8, 0..=65535 for 16-bit sources), or divided by that range under
+/// `ThresholdUnits::Normalized`.
+pub trait WeightMetric: Send + Sync {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32;
+
+    /// Full-scale channel value the metric expects, when it needs a fixed one.
+    /// The builders then rescale samples to `0..=range` whatever their type.
+    fn range(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Euclidean distance in RGB space.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RgbL2;
+
+/// Sum of absolute channel differences.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RgbL1;
+
+/// Largest absolute channel difference.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RgbLInf;
+
+/// CIE 1976 colour difference (Euclidean distance in CIELAB).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeltaE76;
+
+/// CIEDE2000 colour difference.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeltaE2000;
+
+/// Absolute difference of Rec. 601 luma.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Intensity;
+
+impl WeightMetric for RgbL2 {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f32>().sqrt()
+    }
+}
+
+impl WeightMetric for RgbL1 {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+    }
+}
+
+impl WeightMetric for RgbLInf {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).fold(0f32, f32::max)
+    }
+}
+
+impl WeightMetric for DeltaE76 {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        let a = to_lab(a);
+        let b = to_lab(b);
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+    }
+
+    fn range(&self) -> Option<f32> {
+        Some(255f32)
+    }
+}
+
+impl WeightMetric for DeltaE2000 {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        ciede2000(to_lab(a), to_lab(b))
+    }
+
+    fn range(&self) -> Option<f32> {
+        Some(255f32)
+    }
+}
+
+impl WeightMetric for Intensity {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        (luma(a) - luma(b)).abs()
+    }
+}
+
+fn luma(p: &[f32]) -> f32 {
+    0.299 * p[0] + 0.587 * p[1] + 0.114 * p[2]
+}
+
+/// sRGB (0..=255) to CIELAB under D65.
+fn to_lab(p: &[f32]) -> [f32; 3] {
+    fn linear(c: f32) -> f32 {
+        let c = c / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }
+    fn f(t: f32) -> f32 {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    }
+
+    let (r, g, b) = (linear(p[0]), linear(p[1]), linear(p[2]));
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn ciede2000(lab1: [f32; 3], lab2: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lab1.map(f64::from);
+    let [l2, a2, b2] = lab2.map(f64::from);
+
+    let c_bar = ((a1.powi(2) + b1.powi(2)).sqrt() + (a2.powi(2) + b2.powi(2)).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = ((a1.powi(2) + b1.powi(2)).sqrt(), (a2.powi(2) + b2.powi(2)).sqrt());
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let sl = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * c_bar;
+    let sh = 1.0 + 0.015 * c_bar * t;
+    let rt = -2.0 * (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt()
+        * (60.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp()).to_radians().sin();
+
+    let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+    (l.powi(2) + c.powi(2) + h.powi(2) + rt * c * h).sqrt() as f32
+}
+
+/// Edge-weight metric selected in `Params`.
+#[derive(Clone, Default)]
+pub enum Metric {
+    #[default]
+    RgbL2,
+    RgbL1,
+    RgbLInf,
+    DeltaE76,
+    DeltaE2000,
+    Intensity,
+    Custom(Arc<dyn WeightMetric>),
+}
+
+impl WeightMetric for Metric {
+    fn weight(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::RgbL2 => RgbL2.weight(a, b),
+            Metric::RgbL1 => RgbL1.weight(a, b),
+            Metric::RgbLInf => RgbLInf.weight(a, b),
+            Metric::DeltaE76 => DeltaE76.weight(a, b),
+            Metric::DeltaE2000 => DeltaE2000.weight(a, b),
+            Metric::Intensity => Intensity.weight(a, b),
+            Metric::Custom(metric) => metric.weight(a, b),
+        }
+    }
+
+    fn range(&self) -> Option<f32> {
+        match self {
+            Metric::DeltaE76 => DeltaE76.range(),
+            Metric::DeltaE2000 => DeltaE2000.range(),
+            Metric::Custom(metric) => metric.range(),
+            _ => None,
+        }
+    }
+}
+
+impl Metric {
+    /// Bands the metric reads from every pixel; the colour metrics need RGB.
+    pub(crate) fn min_channels(&self) -> usize {
+        match self {
+            Metric::DeltaE76 | Metric::DeltaE2000 | Metric::Intensity => 3,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Debug for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::RgbL2 => write!(f, "RgbL2"),
+            Metric::RgbL1 => write!(f, "RgbL1"),
+            Metric::RgbLInf => write!(f, "RgbLInf"),
+            Metric::DeltaE76 => write!(f, "DeltaE76"),
+            Metric::DeltaE2000 => write!(f, "DeltaE2000"),
+            Metric::Intensity => write!(f, "Intensity"),
+            Metric::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
@@ -1,14 +1,30 @@
-use crate::parallel::graph::Graph;
+use crate::criterion::MinSize;
+use crate::parallel::graph::{Edge, Graph};
+use crate::parallel::lock::{read, write};
+use crate::segmentation::{Phase, Segmentation, Snapshot};
+use crate::tree::MergeTree;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::cmp::Ordering;
 
+#[derive(Default)]
 pub struct Algo;
 
+/// Tile holding the pixel in row `x`, column `y`. The last tile of a row or
+/// column is cut short when the image size is not a multiple of the tile size.
 pub fn get_tile_id (x: usize, y: usize, width: usize, tile_width: usize, tile_height: usize) -> usize {
     let tile_x = x / tile_height;
     let tile_y = y / tile_width;
-    tile_x * (width /tile_width) + tile_y
+    tile_x * width.div_ceil(tile_width) + tile_y
 }
+
+// Total order on edges by `(weight, lower node, higher node)`, the same key the
+// serial engine uses after `Kruskal::canonical_order`.
+fn edge_order (a: &Edge, b: &Edge) -> Ordering {
+    a.weight.total_cmp(&b.weight)
+        .then(a.node1.min(a.node2).cmp(&b.node1.min(b.node2)))
+        .then(a.node1.max(a.node2).cmp(&b.node1.max(b.node2)))
+}
+
 impl Algo {
 
     pub fn new () -> Self {
@@ -16,16 +32,17 @@ impl Algo {
     }
     pub fn threshold_merge (&self, graph: &mut Graph) {
         graph.tiles.par_iter_mut().for_each(|tile| {
-            tile.edges.par_sort_by(|a, b| a.weight.partial_cmp(&b.weight).unwrap());
-            tile.border_edges.par_sort_by(|a, b| a.weight.partial_cmp(&b.weight).unwrap());
+            tile.edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
+            tile.border_edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
 
+            tile.index = tile.edges.len();
             for (ind, edge) in tile.edges.iter().enumerate() {
                 if edge.weight <= graph.dsu.threshold {
                     match graph.dsu.union_threshold(edge.node1, edge.node2, edge.weight) {
                         None => {}
                         Some(node) => {
                             let tile = get_tile_id(edge.node1 / graph.width, edge.node1 % graph.width, graph.width, graph.tile_width, graph.tile_height);
-                            graph.regions[tile].write().unwrap().remove(&node.1);
+                            write(&graph.regions[tile]).remove(&node.1);
                         }
                     }
                 } else {
@@ -36,8 +53,13 @@ impl Algo {
         });
     }
 
+    /// Threshold merge across tile borders, tiles in parallel. The components
+    /// below the threshold do not depend on the order of the unions, and a region
+    /// entry is only ever removed for a root that has been absorbed for good, so
+    /// concurrent tiles cannot drop the mark of a live root.
     pub fn border_edges_merge(&self, graph: &mut Graph) {
-        graph.tiles.iter_mut().for_each(|tile| {
+        graph.tiles.par_iter_mut().for_each(|tile| {
+            tile.border_index = tile.border_edges.len();
             for (ind, edge) in tile.border_edges.iter().enumerate() {
                 if edge.weight <= graph.dsu.threshold {
                     match graph.dsu.union_threshold(edge.node1, edge.node2, edge.weight) {
@@ -46,14 +68,12 @@ impl Algo {
                             let tile1 = get_tile_id(edge.node1 / graph.width, edge.node1 % graph.width, graph.width, graph.tile_width, graph.tile_height);
                             let tile2 = get_tile_id(edge.node2 / graph.width, edge.node2 % graph.width, graph.width, graph.tile_width, graph.tile_height);
 
-                            graph.regions[tile1].write().unwrap().remove(&node.1);
-                            graph.regions[tile2].write().unwrap().remove(&node.1);
+                            write(&graph.regions[tile1]).remove(&node.1);
+                            write(&graph.regions[tile2]).remove(&node.1);
 
-                            if graph.regions[tile1].write().unwrap().contains_key(&node.1) {
-                                graph.regions[tile1].write().unwrap().insert(node.0, true);
-                            } else {
-                                graph.regions[tile2].write().unwrap().insert(node.0, true);
-                            }
+                            // The merged region now spans both tiles
+                            write(&graph.regions[tile1]).insert(node.0, true);
+                            write(&graph.regions[tile2]).insert(node.0, true);
                         }
                     }
                 } else {
@@ -67,64 +87,79 @@ impl Algo {
 
     pub fn compute_credit (&self, graph: &mut Graph) {
         graph.regions.par_iter().for_each(|tile| {
-            let regions = tile.read().unwrap();
+            let regions = read(tile);
             for i in regions.iter() {
                 let credit = graph.dsu.compute_credit(*i.0, 0f32);
-                let mut credit_lock = graph.dsu.credit[*i.0].write().unwrap();
-                *credit_lock = credit;
+                graph.dsu.set_credit(*i.0, credit);
             }
         });
     }
 
+    /// Credit merge of every tile's internal edges above the threshold, tiles in
+    /// parallel. Edges touching a region that spans tiles go to the delay queue.
     pub fn apply_heuristic (&self, graph: &mut Graph) {
         let width = graph.width;  // Precompute value outside the closure
         let tile_width = graph.tile_width;
         let tile_height = graph.tile_height;
+        let dsu = &graph.dsu;
+        let regions = &graph.regions;
+
+        // Union an internal edge, or delay it when either region spans tiles.
+        let internal_edge = |edge: &Edge, delay_queue: &mut Vec<Edge>| {
+            let region1 = dsu.find(edge.node1);
+            let region2 = dsu.find(edge.node2);
+            let tile1 = get_tile_id(edge.node1 / width, edge.node1 % width, width, tile_width, tile_height);
+            // One guard for both lookups: taking the read lock twice on one
+            // thread can deadlock behind a queued writer.
+            let spans_tiles = {
+                let regions = read(&regions[tile1]);
+                regions.get(&region1) == Some(&true) || regions.get(&region2) == Some(&true)
+            };
+            if spans_tiles {
+                delay_queue.push(edge.clone());
+                return;
+            }
+            if let Some(node) = dsu.union(edge.node1, edge.node2, edge.weight) {
+                write(&regions[tile1]).remove(&node.1);
+            }
+        };
 
         graph.tiles.par_iter_mut().for_each(|tile| {
-            tile.border_index = tile.border_edges.binary_search_by(|a| a.weight.partial_cmp(&graph.dsu.threshold).unwrap()).unwrap_or_else(|ind| ind);
+            tile.border_index = tile.border_edges.binary_search_by(|a| a.weight.total_cmp(&dsu.threshold)).unwrap_or_else(|ind| ind);
             while tile.index < tile.edges.len() && tile.border_index < tile.border_edges.len() {
-                // Use precomputed `width` inside the closure
                 if tile.edges[tile.index].weight < tile.border_edges[tile.border_index].weight {
-                    let edge = &tile.edges[tile.index];
-                    let region1 = graph.dsu.find(edge.node1);
-                    let region2 = graph.dsu.find(edge.node2);
-                    let tile1 = get_tile_id(edge.node1 / graph.width, edge.node1 % graph.width, graph.width, graph.tile_width, graph.tile_height);
-                    if graph.regions[tile1].read().unwrap().get(&region1) == Some(&true)
-                        || graph.regions[tile1].read().unwrap().get(&region2) == Some(&true) {
-                        tile.delay_queue.push(edge.clone());
-                        tile.index += 1;
-                        continue;
-                    }
-                    match graph.dsu.union(edge.node1, edge.node2, edge.weight) {
-                        None => {}
-                        Some(node) => {
-                            graph.regions[tile1].write().unwrap().remove(&node.1);
-                        }
-                    }
+                    internal_edge(&tile.edges[tile.index], &mut tile.delay_queue);
                     tile.index += 1;
                 } else {
                     let edge = &tile.border_edges[tile.border_index];
-                    let region1 = graph.dsu.find(edge.node1);
-                    let region2 = graph.dsu.find(edge.node2);
+                    let region1 = dsu.find(edge.node1);
+                    let region2 = dsu.find(edge.node2);
                     let tile1 = get_tile_id(edge.node1 / width, edge.node1 % width, width, tile_width, tile_height);
                     let tile2 = get_tile_id(edge.node2 / width, edge.node2 % width, width, tile_width, tile_height);
-                    graph.regions[tile1].write().unwrap().insert(region1, true);
-                    graph.regions[tile2].write().unwrap().insert(region2, true);
+                    write(&regions[tile1]).insert(region1, true);
+                    write(&regions[tile2]).insert(region2, true);
                     tile.border_index += 1;
                 }
             }
+            // Out of border edges: the remaining internal edges still take part.
+            while tile.index < tile.edges.len() {
+                internal_edge(&tile.edges[tile.index], &mut tile.delay_queue);
+                tile.index += 1;
+            }
         });
     }
 
+    /// Credit merge of the delayed edges, tiles in parallel. Each tile still
+    /// walks its queue lightest edge first; the DSU claims both roots of every
+    /// union, so tiles racing on a shared region cannot lose an update.
     pub fn delay_queue (&self, graph: &mut Graph) {
-        graph.tiles.iter_mut().for_each(|tile| {
+        graph.tiles.par_iter().for_each(|tile| {
             for edge in tile.delay_queue.iter() {
                 let tile1 = get_tile_id(edge.node1 / graph.width, edge.node1 % graph.width, graph.width, graph.tile_width, graph.tile_height);
                 match graph.dsu.union(edge.node1, edge.node2, edge.weight) {
                     None => {}
                     Some(node) => {
-                        graph.regions[tile1].write().unwrap().remove(&node.1);
+                        write(&graph.regions[tile1]).remove(&node.1);
                     }
                 }
             }
@@ -132,52 +167,181 @@ impl Algo {
     }
 
 
-    pub fn relabel (&self, graph: &Graph) -> Vec<Vec<(u8, u8, u8)>> {
-        let image = &graph.pixel;
-        let mut region_colors: HashMap<usize, (f32, f32, f32, i32)> = HashMap::new();
+    /// Felzenszwalb–Huttenlocher merge of every tile's internal edges, tiles in parallel.
+    pub fn felzenszwalb_tiles (&self, graph: &mut Graph) {
+        graph.tiles.par_iter_mut().for_each(|tile| {
+            tile.edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
+            tile.border_edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
 
-        let height = image.len();
-        let width = image[0].len();
+            for edge in tile.edges.iter() {
+                graph.dsu.union(edge.node1, edge.node2, edge.weight);
+            }
+        });
+    }
 
-        for y in 0..height {
-            for x in 0..width {
-                let region_id = graph.dsu.find(y * width + x);
-                let (r, g, b) = image[y][x];
+    /// Continue the Felzenszwalb–Huttenlocher merge across tile borders, lightest edge first.
+    pub fn felzenszwalb_borders (&self, graph: &mut Graph) {
+        let mut edges: Vec<&Edge> = graph.tiles.iter().flat_map(|tile| tile.border_edges.iter()).collect();
+        edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
 
-                let entry = region_colors.entry(region_id).or_insert((0.0, 0.0, 0.0, 0));
-                entry.0 += r as f32;
-                entry.1 += g as f32;
-                entry.2 += b as f32;
-                entry.3 += 1;
-            }
+        for edge in edges {
+            graph.dsu.union(edge.node1, edge.node2, edge.weight);
         }
+    }
 
-        let mut result_image = vec![vec![(0, 0, 0); width]; height];
+    /// Merge every component smaller than `min_size` into the neighbour across
+    /// its lightest edge.
+    pub fn merge_small (&self, graph: &mut Graph, min_size: u32) {
+        let criterion = MinSize { min_size };
+        let mut edges: Vec<&Edge> = graph.tiles.iter()
+            .flat_map(|tile| tile.edges.iter().chain(tile.border_edges.iter()))
+            .collect();
+        edges.par_sort_by(|a, b| a.weight.total_cmp(&b.weight));
 
-        for y in 0..height {
-            for x in 0..width {
-                let region_id = graph.dsu.find(y * width + x);
-                let (r_sum, g_sum, b_sum, count) = region_colors[&region_id];
+        for edge in edges {
+            graph.dsu.union_with(edge.node1, edge.node2, edge.weight, &criterion);
+        }
+    }
 
-                let avg_r = (r_sum / count as f32) as u8;
-                let avg_g = (g_sum / count as f32) as u8;
-                let avg_b = (b_sum / count as f32) as u8;
+    /// Tiled Felzenszwalb–Huttenlocher segmentation. The graph's DSU must have
+    /// been built with the `Felzenszwalb` criterion.
+    pub fn felzenszwalb (&self, graph: &mut Graph, min_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
+
+        self.felzenszwalb_tiles(graph);
+        self.felzenszwalb_borders(graph);
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::Felzenszwalb, segmentation: self.segmentation(graph) });
+        }
+
+        self.merge_small(graph, min_size);
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::MinSize, segmentation: self.segmentation(graph) });
+        }
+
+        let mut segmentation = self.segmentation(graph);
+        segmentation.snapshots = phases;
+        segmentation
+    }
 
-                result_image[y][x] = (avg_r, avg_g, avg_b);
+    /// Every edge of the graph, tile and border edges alike, in `edge_order`.
+    pub fn sorted_edges (&self, graph: &Graph) -> Vec<Edge> {
+        let mut edges: Vec<Edge> = graph.tiles.iter()
+            .flat_map(|tile| tile.edges.iter().chain(tile.border_edges.iter()))
+            .cloned()
+            .collect();
+        edges.par_sort_unstable_by(edge_order);
+        edges
+    }
+
+    /// PHMSF with a result that does not depend on scheduling, tiling or thread
+    /// count, and equals the serial engine's after `Kruskal::canonical_order`.
+    ///
+    /// The threshold phase still runs per tile in parallel, since the components
+    /// of the edges below the threshold do not depend on the order they are
+    /// joined in. Unless scribbles can refuse unions: then it runs in
+    /// `edge_order` too. The credit and minimum-size phases walk all edges
+    /// sequentially in `edge_order`.
+    pub fn apply_deterministic (&self, graph: &mut Graph, min_region_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
+        let mut snapshot = |phase: Phase, graph: &Graph| {
+            if snapshots {
+                phases.push(Snapshot { phase, segmentation: self.segmentation(graph) });
+            }
+        };
+
+        let edges = self.sorted_edges(graph);
+        let split = edges.partition_point(|edge| edge.weight <= graph.dsu.threshold);
+        if graph.dsu.is_constrained() {
+            for edge in &edges[..split] {
+                graph.dsu.union_threshold(edge.node1, edge.node2, edge.weight);
             }
+            snapshot(Phase::Threshold, graph);
+        } else {
+            self.threshold_merge(graph);
+            snapshot(Phase::Threshold, graph);
+            self.border_edges_merge(graph);
+            snapshot(Phase::BorderMerge, graph);
+        }
+
+        for edge in &edges[split..] {
+            graph.dsu.union(edge.node1, edge.node2, edge.weight);
+        }
+        snapshot(Phase::Credit, graph);
+
+        if min_region_size > 1 {
+            let criterion = MinSize { min_size: min_region_size };
+            for edge in &edges {
+                graph.dsu.union_with(edge.node1, edge.node2, edge.weight, &criterion);
+            }
+            snapshot(Phase::MinSize, graph);
+        }
+
+        let mut segmentation = self.segmentation(graph);
+        segmentation.snapshots = phases;
+        segmentation
+    }
+
+    /// Felzenszwalb–Huttenlocher over all edges in `edge_order`, matching the
+    /// serial engine after `Kruskal::canonical_order`.
+    pub fn felzenszwalb_deterministic (&self, graph: &mut Graph, min_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
+        let edges = self.sorted_edges(graph);
+
+        for edge in &edges {
+            graph.dsu.union(edge.node1, edge.node2, edge.weight);
+        }
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::Felzenszwalb, segmentation: self.segmentation(graph) });
+        }
+
+        let criterion = MinSize { min_size };
+        for edge in &edges {
+            graph.dsu.union_with(edge.node1, edge.node2, edge.weight, &criterion);
         }
+        if snapshots {
+            phases.push(Snapshot { phase: Phase::MinSize, segmentation: self.segmentation(graph) });
+        }
+
+        let mut segmentation = self.segmentation(graph);
+        segmentation.snapshots = phases;
+        segmentation
+    }
 
-        result_image
+    /// Tree of the unions performed since `graph.dsu.record_merges()` was called.
+    pub fn merge_tree (&self, graph: &Graph) -> MergeTree {
+        MergeTree::from_unions(graph.width, graph.height, &graph.dsu.take_merges())
     }
 
-    pub fn apply (&self, graph: &mut Graph) -> Vec<Vec<(u8, u8, u8)>> {
+    pub fn segmentation (&self, graph: &Graph) -> Segmentation {
+        Segmentation::from_roots(graph.width, graph.height, |node| graph.dsu.find(node))
+    }
+
+    pub fn apply (&self, graph: &mut Graph, min_region_size: u32, snapshots: bool) -> Segmentation {
+        let mut phases = Vec::new();
+        let mut snapshot = |phase: Phase, graph: &Graph| {
+            if snapshots {
+                phases.push(Snapshot { phase, segmentation: self.segmentation(graph) });
+            }
+        };
+
         self.threshold_merge(graph);
+        snapshot(Phase::Threshold, graph);
         self.border_edges_merge(graph);
-        let ans = self.relabel(graph);
+        snapshot(Phase::BorderMerge, graph);
         self.compute_credit(graph);
         self.apply_heuristic(graph);
+        snapshot(Phase::Heuristic, graph);
         self.delay_queue(graph);
-        ans
+        snapshot(Phase::DelayQueue, graph);
+        if min_region_size > 1 {
+            self.merge_small(graph, min_region_size);
+            snapshot(Phase::MinSize, graph);
+        }
+
+        let mut segmentation = self.segmentation(graph);
+        segmentation.snapshots = phases;
+        segmentation
     }
 
 }
\ No newline at end of file
@@ -1,137 +1,290 @@
+use std::collections::HashMap;
+use std::hint::spin_loop;
+use std::thread::yield_now;
 use std::mem::swap;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::criterion::{Component, Credit, MergeCriterion, MustLink, Threshold};
+use crate::graph::dsu::NO_SEED;
+use crate::parallel::lock::lock;
 
-/// Thread-safe Disjoint Set Union
+// Set in a root's parent entry while a union owns that root.
+const CLAIMED: usize = 1 << (usize::BITS - 1);
+
+// Failed claims to spin through before yielding the thread instead.
+const SPIN_LIMIT: u32 = 64;
+
+fn back_off(spins: &mut u32) {
+    if *spins < SPIN_LIMIT {
+        *spins += 1;
+        spin_loop();
+    } else {
+        yield_now();
+    }
+}
+
+/// Concurrent Disjoint Set Union with per-root spin claims.
+///
+/// `find` is lock-free: parents are read atomically and shortened by path
+/// halving with CAS. The statistics of a root (`size`, `smallest_edge`,
+/// `credit`, `seed`) are only written by the union that has claimed it: a claim
+/// CASes the root's parent entry from `root` to `root | CLAIMED`, which fails
+/// unless the node is an unclaimed root, and is released by storing a plain
+/// parent again. A claim is thus a small spinlock on one root. A union claims
+/// both roots in index order, backing off and retrying when the second one is
+/// taken, so no two unions ever touch the same root at once and no update is
+/// lost. Waiters spin briefly and then yield, so a preempted holder does not
+/// keep the others busy.
 pub struct DisjointSetUnion {
-    parent: Vec<RwLock<usize>>,
-    smallest_edge: Vec<RwLock<f32>>,
-    pub(crate) size: Vec<RwLock<i32>>,
-    pub(crate) credit: Vec<RwLock<f32>>,
+    parent: Vec<AtomicUsize>,
+    smallest_edge: Vec<AtomicU32>, // f32 bits
+    size: Vec<AtomicU32>,
+    credit: Vec<AtomicU32>, // f32 bits
+    seed: Vec<AtomicU32>,
     pub threshold: f32,
-    contrast: f32,
+    criterion: Arc<dyn MergeCriterion>,
+    recording: AtomicBool,
+    constrained: AtomicBool,
+    merges: Mutex<Vec<(usize, usize, f32)>>,
+}
+
+fn load_f32(value: &AtomicU32) -> f32 {
+    f32::from_bits(value.load(Ordering::Relaxed))
+}
+
+fn store_f32(value: &AtomicU32, x: f32) {
+    value.store(x.to_bits(), Ordering::Relaxed);
 }
 
 impl DisjointSetUnion {
     pub fn new(nodes: usize, threshold: f32, contrast: f32) -> Arc<Self> {
+        DisjointSetUnion::with_criterion(nodes, threshold, Arc::new(Credit { contrast }))
+    }
+
+    pub fn with_criterion(nodes: usize, threshold: f32, criterion: Arc<dyn MergeCriterion>) -> Arc<Self> {
         Arc::new(DisjointSetUnion {
-            parent: (0..nodes).map(|i| RwLock::new(i)).collect(),
-            smallest_edge: (0..nodes).map(|_| RwLock::new(f32::NAN)).collect(),
-            size: (0..nodes).map(|_| RwLock::new(1)).collect(),
-            credit: (0..nodes).map(|_| RwLock::new(f32::NAN)).collect(),
+            parent: (0..nodes).map(AtomicUsize::new).collect(),
+            smallest_edge: (0..nodes).map(|_| AtomicU32::new(f32::NAN.to_bits())).collect(),
+            size: (0..nodes).map(|_| AtomicU32::new(1)).collect(),
+            credit: (0..nodes).map(|_| AtomicU32::new(f32::NAN.to_bits())).collect(),
+            seed: (0..nodes).map(|_| AtomicU32::new(NO_SEED)).collect(),
             threshold,
-            contrast,
+            criterion,
+            recording: AtomicBool::new(false),
+            constrained: AtomicBool::new(false),
+            merges: Mutex::new(Vec::new()),
         })
     }
 
+    /// Log every successful union from now on, for building a merge tree.
+    pub fn record_merges(&self) {
+        self.recording.store(true, Ordering::Relaxed);
+    }
+
+    /// Unions logged so far as `(kept root, absorbed root, weight)`.
+    pub fn take_merges(&self) -> Vec<(usize, usize, f32)> {
+        std::mem::take(&mut *lock(&self.merges))
+    }
+
+    /// Label the `(node, label)` seeds and join all nodes that share a label.
+    /// Unions between components with different labels are refused from now on.
+    /// Returns the unions performed as `(root, absorbed root)`.
+    pub fn constrain(&self, seeds: &[(usize, u32)]) -> Vec<(usize, usize)> {
+        let mut first = HashMap::new();
+        let mut unions = Vec::new();
+        if !seeds.is_empty() {
+            self.constrained.store(true, Ordering::Relaxed);
+        }
+        for &(node, label) in seeds {
+            let root = self.claim(node);
+            if self.seed[root].load(Ordering::Relaxed) == NO_SEED {
+                self.seed[root].store(label, Ordering::Relaxed);
+            }
+            self.release(root);
+            let head = *first.entry(label).or_insert(node);
+            unions.extend(self.union_with(head, node, 0f32, &MustLink));
+        }
+        unions
+    }
+
+    /// Whether `constrain` has been given any seeds.
+    pub fn is_constrained(&self) -> bool {
+        self.constrained.load(Ordering::Relaxed)
+    }
+
     pub fn find(&self, node: usize) -> usize {
         let mut current = node;
 
         loop {
-            let parent = *self.parent[current].read().unwrap();
+            let parent = self.parent[current].load(Ordering::Acquire) & !CLAIMED;
             if parent == current {
-                break;
+                return current;
             }
 
-            // Path compression: Point current node to its grandparent
-            let grandparent = *self.parent[parent].read().unwrap();
-            *self.parent[current].write().unwrap() = grandparent;
+            // Path halving: point the node at its grandparent. Only non-roots are
+            // rewritten here, so losing the race to another halving is harmless.
+            let grandparent = self.parent[parent].load(Ordering::Acquire) & !CLAIMED;
+            let _ = self.parent[current].compare_exchange(parent, grandparent, Ordering::AcqRel, Ordering::Relaxed);
 
-            current = parent;
+            current = grandparent;
         }
-
-        current
     }
 
-    pub fn compute_credit(&self, node: usize, weight: f32) -> f32 {
-        let par = self.find(node);
+    fn try_claim(&self, root: usize) -> bool {
+        self.parent[root].compare_exchange(root, root | CLAIMED, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
 
-        let min_perimeter: f32 = (4.0 * std::f32::consts::PI * *self.size[par].read().unwrap() as f32).sqrt();
+    fn release(&self, root: usize) {
+        self.parent[root].store(root, Ordering::Release);
+    }
 
-        let contrast = {
-            let smallest_edge = *self.smallest_edge[node].read().unwrap();
-            if smallest_edge.is_nan() {
-                weight
-            } else {
-                smallest_edge
+    /// Claim the root of `node`, waiting while another union holds it.
+    fn claim(&self, node: usize) -> usize {
+        let mut spins = 0;
+        loop {
+            let root = self.find(node);
+            if self.try_claim(root) {
+                return root;
             }
-        } - 2f32 * self.contrast;
-
-        contrast * min_perimeter
+            back_off(&mut spins);
+        }
     }
 
-    pub fn union(&self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)> {
-        let mut u = self.find(u);
-        let mut v = self.find(v);
-
-        if u != v {
-            let credit_u = {
-                let mut credit = self.credit[u].write().unwrap();
-                if credit.is_nan() {
-                    *credit = self.compute_credit(u, weight);
-                }
-                *credit
-            };
+    // Takes the score separately so callers can pass one that is not stored yet.
+    fn component(&self, root: usize, score: f32) -> Component {
+        Component {
+            size: self.size[root].load(Ordering::Relaxed),
+            smallest_edge: load_f32(&self.smallest_edge[root]),
+            score,
+        }
+    }
 
-            let credit_v = {
-                let mut credit = self.credit[v].write().unwrap();
-                if credit.is_nan() {
-                    *credit = self.compute_credit(v, weight);
-                }
-                *credit
-            };
+    pub fn compute_credit(&self, node: usize, weight: f32) -> f32 {
+        let root = self.claim(node);
+        let credit = self.criterion.init(&self.component(root, f32::NAN), weight);
+        self.release(root);
+        credit
+    }
 
-            let credit = credit_u.min(credit_v);
+    /// Overwrite the credit stored at `node`. Only called between merge phases,
+    /// when no union is running.
+    pub(crate) fn set_credit(&self, node: usize, credit: f32) {
+        store_f32(&self.credit[node], credit);
+    }
 
-            if credit > weight {
-                if *self.size[u].read().unwrap() < *self.size[v].read().unwrap() {
-                    swap(&mut u, &mut v);
-                }
+    /// Union under the configured merge criterion.
+    pub fn union(&self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)> {
+        self.union_with(u, v, weight, self.criterion.as_ref())
+    }
 
-                // Perform union
-                *self.parent[v].write().unwrap() = u;
+    pub fn union_threshold(&self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)>  {
+        self.union_with(u, v, weight, &Threshold { threshold: self.threshold })
+    }
 
-                let mut size_u = self.size[u].write().unwrap();
-                let size_v = *self.size[v].read().unwrap();
-                *size_u += size_v;
+    /// Returns `(root, absorbed root)` when the two components were merged.
+    pub fn union_with(&self, u: usize, v: usize, weight: f32, criterion: &dyn MergeCriterion) -> Option<(usize, usize)> {
+        let mut spins = 0;
+        let (u, v) = loop {
+            let (u, v) = (self.find(u), self.find(v));
+            if u == v {
+                return None;
+            }
 
-                let mut smallest_edge_u = self.smallest_edge[u].write().unwrap();
-                let smallest_edge_v = *self.smallest_edge[v].read().unwrap();
-                *smallest_edge_u = smallest_edge_u.min(smallest_edge_v);
+            // Claim both roots, lower index first; back off if the second is taken.
+            let (first, second) = (u.min(v), u.max(v));
+            if self.try_claim(first) {
+                if self.try_claim(second) {
+                    break (u, v);
+                }
+                self.release(first);
+            }
+            back_off(&mut spins);
+        };
 
-                let mut credit_u = self.credit[u].write().unwrap();
-                *credit_u = credit - weight;
-                Some((u, v))
-            } else {
+        match self.merge_claimed(u, v, weight, criterion) {
+            None => {
+                self.release(u);
+                self.release(v);
                 None
             }
-
-        } else {
-            None
+            Some((root, absorbed)) => {
+                // Linking the absorbed root also drops its claim. The kept root is
+                // released last, once its statistics are complete and the merge is
+                // logged, so no later union of `root` can be logged before this one.
+                self.parent[absorbed].store(root, Ordering::Release);
+                if self.recording.load(Ordering::Relaxed) {
+                    lock(&self.merges).push((root, absorbed, weight));
+                }
+                self.release(root);
+                Some((root, absorbed))
+            }
         }
     }
 
-    pub fn union_threshold(&self, u: usize, v: usize, weight: f32) -> Option<(usize, usize)>  {
-        let mut u = self.find(u);
-        let mut v = self.find(v);
+    // Both roots are claimed by the caller. Updates the kept root's statistics
+    // and returns `(root, absorbed root)`, or `None` to keep them apart.
+    fn merge_claimed(&self, mut u: usize, mut v: usize, weight: f32, criterion: &dyn MergeCriterion) -> Option<(usize, usize)> {
+        let (seed_u, seed_v) = (self.seed[u].load(Ordering::Relaxed), self.seed[v].load(Ordering::Relaxed));
+        if seed_u != NO_SEED && seed_v != NO_SEED && seed_u != seed_v {
+            return None;
+        }
 
-        if u != v && weight < self.threshold {
-            if *self.size[u].read().unwrap() < *self.size[v].read().unwrap() {
-                swap(&mut u, &mut v);
-            }
+        let mut credit_u = load_f32(&self.credit[u]);
+        if credit_u.is_nan() {
+            credit_u = criterion.init(&self.component(u, f32::NAN), weight);
+            store_f32(&self.credit[u], credit_u);
+        }
+        let mut credit_v = load_f32(&self.credit[v]);
+        if credit_v.is_nan() {
+            credit_v = criterion.init(&self.component(v, f32::NAN), weight);
+            store_f32(&self.credit[v], credit_v);
+        }
+
+        let credit = criterion.merge(&self.component(u, credit_u), &self.component(v, credit_v), weight)?;
+
+        if self.size[u].load(Ordering::Relaxed) < self.size[v].load(Ordering::Relaxed) {
+            swap(&mut u, &mut v);
+        }
+
+        self.size[u].fetch_add(self.size[v].load(Ordering::Relaxed), Ordering::Relaxed);
+        let smallest_edge = load_f32(&self.smallest_edge[u]).min(load_f32(&self.smallest_edge[v]));
+        store_f32(&self.smallest_edge[u], smallest_edge);
+        store_f32(&self.credit[u], credit);
+        if self.seed[u].load(Ordering::Relaxed) == NO_SEED {
+            self.seed[u].store(self.seed[v].load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        Some((u, v))
+    }
+}
 
-            // Perform union
-            *self.parent[v].write().unwrap() = u;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
 
-            let mut size_u = self.size[u].write().unwrap();
-            let size_v = *self.size[v].read().unwrap();
-            *size_u += size_v;
+    #[test]
+    fn concurrent_unions_keep_every_node() {
+        let nodes = 1 << 14;
+        let dsu = DisjointSetUnion::new(nodes, 1f32, 0f32);
+        dsu.record_merges();
+        // Each node joins a few far-apart partners, so threads keep racing on the same roots.
+        (0..nodes * 4).into_par_iter().for_each(|i| {
+            let u = i % nodes;
+            let v = (i * 7919 + 13) % nodes;
+            dsu.union_threshold(u, v, 0f32);
+        });
 
-            let mut smallest_edge_u = self.smallest_edge[u].write().unwrap();
-            let smallest_edge_v = *self.smallest_edge[v].read().unwrap();
-            *smallest_edge_u = smallest_edge_u.min(smallest_edge_v);
+        let roots: Vec<usize> = (0..nodes).filter(|&node| dsu.find(node) == node).collect();
+        let sizes: u32 = roots.iter().map(|&root| dsu.size[root].load(Ordering::Relaxed)).sum();
+        assert_eq!(sizes as usize, nodes);
 
-            return Some((u, v));
+        // The log must read as a sequential history: an absorbed root never shows up again.
+        let merges = dsu.take_merges();
+        assert_eq!(merges.len(), nodes - roots.len());
+        let mut absorbed = vec![false; nodes];
+        for (root, child, _) in merges {
+            assert!(!absorbed[root] && !absorbed[child]);
+            absorbed[child] = true;
         }
-        None
     }
 }
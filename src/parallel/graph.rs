@@ -1,5 +1,16 @@
+use crate::criterion::{Credit, MergeCriterion};
+use crate::error::{check_dimensions, SegmentError};
+use crate::parallel::algo::get_tile_id;
 use crate::parallel::dsu::DisjointSetUnion;
-use image::GenericImageView;
+use crate::parallel::lock::write;
+use crate::histogram::WeightHistogram;
+use crate::mask::effective_mask;
+use crate::metric::WeightMetric;
+use crate::params::Params;
+use crate::sample::Sample;
+use crate::source::ImageSource;
+use image::{DynamicImage, RgbImage};
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
@@ -27,12 +38,20 @@ pub struct Graph {
     pub tile_height: usize,
     pub regions: Arc<Vec<RwLock<HashMap<usize, bool>>>>,
     pub dsu: Arc<DisjointSetUnion>,
-    pub pixel: Vec<Vec<(u8, u8, u8)>>,
+    /// Samples of every pixel in native units, row-major, `channels` per pixel.
+    pub pixels: Vec<f32>,
+    pub channels: usize,
+    pub histogram: WeightHistogram,
 }
 
 impl Graph {
     pub fn new(width: usize, height: usize, tile_width: usize, tile_height: usize, threshold: f32, contrast: f32) -> Self {
-        let num_tiles = (width / tile_width) * (height / tile_height);
+        Graph::with_criterion(width, height, tile_width, tile_height, threshold, Arc::new(Credit { contrast }))
+    }
+
+    pub fn with_criterion(width: usize, height: usize, tile_width: usize, tile_height: usize, threshold: f32, criterion: Arc<dyn MergeCriterion>) -> Self {
+        // Partial tiles cover the right and bottom edges.
+        let num_tiles = width.div_ceil(tile_width) * height.div_ceil(tile_height);
         let tiles = vec![
             Tile {
                 edges: Vec::new(),
@@ -43,7 +62,7 @@ impl Graph {
             };
             num_tiles
         ];
-        let dsu = DisjointSetUnion::new(width*height, threshold, contrast);
+        let dsu = DisjointSetUnion::with_criterion(width*height, threshold, criterion);
         let mut v = vec![];
         for _ in 0..num_tiles {
             v.push(RwLock::new(HashMap::new()));
@@ -57,20 +76,58 @@ impl Graph {
             tile_height,
             dsu,
             regions,
-            pixel: vec![vec![(0, 0, 0); width]; height],
+            pixels: vec![0f32; width * height * 3],
+            channels: 3,
+            histogram: WeightHistogram::default(),
         }
     }
 
+    pub fn from_image(image: &DynamicImage, params: &Params) -> Result<Self, SegmentError> {
+        graph_from_image_with_tiles(image, params)
+    }
+
+    pub fn from_rgb_image(image: &RgbImage, params: &Params) -> Result<Self, SegmentError> {
+        graph_from_image_with_tiles(image, params)
+    }
+
+    /// Build from rows of packed RGB pixels that start `stride` samples apart.
+    pub fn from_raw<S: Sample>(data: &[S], width: u32, height: u32, stride: usize, params: &Params) -> Result<Self, SegmentError> {
+        graph_from_image_with_tiles(ImageSource::from_raw(data, width, height, stride)?, params)
+    }
+
+    /// Samples of the pixel at column `x`, row `y`.
+    pub fn pixel(&self, x: usize, y: usize) -> &[f32] {
+        let i = (y * self.width + x) * self.channels;
+        &self.pixels[i..i + self.channels]
+    }
+
     pub fn add_edge(&mut self, tile_idx: usize, node1: usize, node2: usize, weight: f32) {
         self.tiles[tile_idx].edges.push(Edge { node1, node2, weight });
+        self.histogram.add(weight);
     }
 
     pub fn add_border_edge (&mut self, tile_idx: usize, node1: usize, node2: usize, weight: f32) {
         self.tiles[tile_idx].border_edges.push(Edge { node1, node2, weight });
+        self.histogram.add(weight);
+    }
+
+    /// Join the `(node, label)` scribble seeds and keep different labels apart
+    /// in every later phase.
+    pub fn constrain(&mut self, seeds: &[(usize, u32)]) {
+        for (root, absorbed) in self.dsu.constrain(seeds) {
+            let tile1 = get_tile_id(root / self.width, root % self.width, self.width, self.tile_width, self.tile_height);
+            let tile2 = get_tile_id(absorbed / self.width, absorbed % self.width, self.width, self.tile_width, self.tile_height);
+            write(&self.regions[tile2]).remove(&absorbed);
+            if tile1 != tile2 {
+                // The seeded region now spans both tiles
+                write(&self.regions[tile1]).insert(root, true);
+                write(&self.regions[tile2]).insert(root, true);
+            }
+        }
     }
 
     pub fn add_region(&mut self, tile_idx: usize, region: usize) {
-        self.regions[tile_idx].write().unwrap().insert(region, false);
+        write(&self.regions[tile_idx]).insert(region, false);
     }
 }
 
@@ -80,60 +137,91 @@ pub fn load_graph_from_image_with_tiles(
     tile_height: usize,
     threshold: f32,
     contrast: f32
-) -> Result<Graph, Box<dyn std::error::Error>> {
+) -> Result<Graph, SegmentError> {
     let image = image::open(path)?;
+    let params = Params { tile_width, tile_height, threshold, contrast, ..Params::default() };
+    graph_from_image_with_tiles(&image, &params)
+}
+
+pub fn graph_from_image_with_tiles<'a, S: Sample>(image: impl Into<ImageSource<'a, S>>, params: &Params) -> Result<Graph, SegmentError> {
+    let image = image.into();
+    let (tile_width, tile_height) = (params.tile_width, params.tile_height);
+    let metric = &params.metric;
     let (width, height) = image.dimensions();
+    check_dimensions(width, height)?;
+    params.connectivity.check(width, height)?;
+    let offsets = params.connectivity.offsets();
+    if tile_width == 0 || tile_height == 0 {
+        return Err(SegmentError::invalid("tile size", "must be greater than zero"));
+    }
+    let mask = effective_mask(&image, params)?;
+    let mask = mask.as_deref();
+    let kept = |col: u32, row: u32| mask.is_none_or(|mask| mask.contains(col, row));
+
+    let (features, dims) = image.features(params)?;
+    let feature = |node: usize| &features[node * dims..(node + 1) * dims];
+
+    let mut graph = Graph::with_criterion(width as usize, height as usize, tile_width, tile_height, params.threshold, params.merge_criterion());
+    graph.channels = image.channels();
+    graph.pixels = image.values();
+
+    // Every tile scans its own rows and columns and fills its own buffers, so the
+    // tiles are built in parallel. Each keeps a histogram of its edges, merged once
+    // all are done.
+    let tiles_across = (width as usize).div_ceil(tile_width);
+    graph.histogram = graph.tiles.par_iter_mut()
+        .zip(graph.regions.par_iter())
+        .enumerate()
+        .map(|(tile_idx, (tile, regions))| {
+            let mut regions = write(regions);
+            let mut histogram = WeightHistogram::default();
+            let rows = (tile_idx / tiles_across) * tile_height..((tile_idx / tiles_across + 1) * tile_height).min(height as usize);
+            let cols = (tile_idx % tiles_across) * tile_width..((tile_idx % tiles_across + 1) * tile_width).min(width as usize);
+
+            for x in rows {
+                for y in cols.clone() {
+                    let neighbours = offsets.iter().map(|&(dx, dy)| (x as i64 + dx as i64, y as i64 + dy as i64));
+
+                    let node1 = x * width as usize + y;
+
+                    regions.insert(node1, false);
+                    if !kept(y as u32, x as u32) {
+                        continue;
+                    }
 
-    let mut graph = Graph::new(width as usize, height as usize, tile_width, tile_height, threshold, contrast);
-
-    for ux in 0..height {
-        for uy in 0..width {
-            let x = ux as usize;
-            let y = uy as usize;
-
-            let tile_x = x / tile_height;
-            let tile_y = y / tile_width;
-            let tile_idx = tile_x * (width as usize / tile_width) + tile_y;
-
-            let neighbours: Vec<(i32, i32)> = vec![
-                (x as i32 + 1, y as i32),
-                (x as i32, y as i32 + 1),
-                (x as i32 + 1, y as i32 + 1),
-                (x as i32 + 1, y as i32 - 1),
-            ];
-
-            let pixel1 = image.get_pixel(uy, ux);
-            graph.pixel[x][y] = (pixel1[0], pixel1[1], pixel1[2]);
-            let node1 = x * width as usize + y;
-
-            graph.add_region(tile_idx, node1);
-
-            for (nx, ny) in neighbours {
-                if nx >= 0
-                    && nx < height as i32
-                    && ny >= 0
-                    && ny < width as i32
-                {
-                    let pixel2 = image.get_pixel(ny as u32, nx as u32);
-                    let node2 = nx as usize * width as usize + ny as usize;
-
-                    let mut weight: f32 = (pixel1[0] as f32 - pixel2[0] as f32).powi(2)
-                        + (pixel1[1] as f32 - pixel2[1] as f32).powi(2)
-                        + (pixel1[2] as f32 - pixel2[2] as f32).powi(2);
-                    weight = weight.sqrt();
-
-                    let neighbour_tile_x = nx as usize / tile_height;
-                    let neighbour_tile_y = ny as usize / tile_width;
-                    let neighbour_tile_idx = neighbour_tile_x * (width as usize / tile_width) + neighbour_tile_y;
-
-                    if tile_idx == neighbour_tile_idx {
-                        graph.add_edge(tile_idx, node1, node2, weight);
-                    } else {
-                        graph.add_border_edge(tile_idx, node1, node2, weight);
+                    for (nx, ny) in neighbours {
+                        if nx >= 0
+                            && nx < height as i64
+                            && ny >= 0
+                            && ny < width as i64
+                            && kept(ny as u32, nx as u32)
+                        {
+                            let node2 = nx as usize * width as usize + ny as usize;
+
+                            let weight = metric.weight(feature(node1), feature(node2));
+                            histogram.add(weight);
+
+                            let neighbour_tile_idx = get_tile_id(nx as usize, ny as usize, width as usize, tile_width, tile_height);
+
+                            if tile_idx == neighbour_tile_idx {
+                                tile.edges.push(Edge { node1, node2, weight });
+                            } else {
+                                tile.border_edges.push(Edge { node1, node2, weight });
+                            }
+                        }
                     }
                 }
             }
-        }
+            histogram
+        })
+        .reduce(WeightHistogram::default, |mut histogram, other| {
+            histogram.merge(&other);
+            histogram
+        });
+
+    if let Some(auto) = params.auto_threshold {
+        let threshold = auto.select(&graph.histogram);
+        graph.dsu = DisjointSetUnion::with_criterion(width as usize * height as usize, threshold, params.merge_criterion());
     }
 
     Ok(graph)
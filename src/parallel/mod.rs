@@ -1,3 +1,4 @@
 pub mod graph;
 pub mod dsu;
-pub(crate) mod algo;
\ No newline at end of file
+pub mod algo;
+mod lock;
@@ -0,0 +1,221 @@
+use crate::connectivity::Connectivity;
+use crate::criterion::{Criterion, Felzenszwalb, MergeCriterion};
+use crate::error::SegmentError;
+use crate::histogram::WeightHistogram;
+use crate::mask::Mask;
+use crate::metric::Metric;
+use crate::scribbles::Scribbles;
+use std::sync::Arc;
+
+/// Which implementation of the segmenter to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Single-threaded Kruskal over the whole image (`graph::kruskal::Kruskal`).
+    Serial,
+    /// Tiled, rayon-parallel segmenter (`parallel::algo::Algo`).
+    Parallel,
+}
+
+/// Which segmentation algorithm the engine runs.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    /// Threshold phase followed by the credit-based merge phase.
+    #[default]
+    Phmsf,
+    /// Felzenszwalb–Huttenlocher graph-based segmentation with scale `k`,
+    /// followed by merging components smaller than `min_size`.
+    Felzenszwalb { k: f32, min_size: u32 },
+}
+
+/// What the graph builders do with the alpha channel of the source.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaPolicy {
+    /// Segment the colour channels only, as if every pixel were opaque.
+    #[default]
+    Ignore,
+    /// Treat fully transparent pixels as lying outside the `mask`.
+    MaskTransparent,
+    /// Hand alpha, multiplied by this weight, to the metric as a fourth channel.
+    /// Only the channel-wise metrics (`RgbL2`, `RgbL1`, `RgbLInf`) look at it.
+    Channel(f32),
+}
+
+/// Units of `threshold`, `contrast` and `k`, i.e. of the channel values the
+/// metric sees. Metrics with a fixed range, such as the CIELAB ones, ignore this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThresholdUnits {
+    /// The sample type's own range: 0..=255 for `u8`, 0..=65535 for `u16`.
+    #[default]
+    Native,
+    /// Every channel scaled to 0..=1, so one setting fits every sample type.
+    Normalized,
+}
+
+/// How to derive `threshold` from the distribution of edge weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoThreshold {
+    /// Otsu's split between flat-area and boundary weights.
+    Otsu,
+    /// The given percentile (0..=100) of the edge weights.
+    Percentile(f32),
+    /// `median + factor * σ`, with the noise level σ estimated as 1.4826 × MAD.
+    Mad { factor: f32 },
+}
+
+impl AutoThreshold {
+    pub fn select(&self, histogram: &WeightHistogram) -> f32 {
+        match *self {
+            AutoThreshold::Otsu => histogram.otsu(),
+            AutoThreshold::Percentile(percent) => histogram.percentile(percent),
+            AutoThreshold::Mad { factor } => histogram.median() + factor * 1.4826 * histogram.mad(),
+        }
+    }
+}
+
+/// Aim for a region count instead of a fixed threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetRegions {
+    pub count: usize,
+    /// Also tune the contrast once the best threshold is found.
+    pub search_contrast: bool,
+}
+
+/// Parameters shared by both engines.
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub engine: Engine,
+    pub algorithm: Algorithm,
+    /// Edges lighter than this are merged unconditionally in the first phase.
+    pub threshold: f32,
+    /// Whether `threshold` and the other weight-valued settings are in native
+    /// sample units or normalized to 0..=1.
+    pub threshold_units: ThresholdUnits,
+    /// Replace `threshold` by a value computed from the edge-weight histogram.
+    /// Leave it `None` to use `threshold` as given. The value chosen is reported
+    /// in the resulting `Segmentation`.
+    pub auto_threshold: Option<AutoThreshold>,
+    /// Contrast term of the credit-based merge phase.
+    pub contrast: f32,
+    /// Rule deciding merges after the threshold phase. Ignored by `Algorithm::Felzenszwalb`.
+    pub criterion: Criterion,
+    /// Choose `threshold` (and optionally `contrast`) automatically to get close to
+    /// this many regions. The search runs on the serial engine; the chosen values
+    /// are reported in the resulting `Segmentation`.
+    pub target_regions: Option<TargetRegions>,
+    /// Components smaller than this are merged into their most similar neighbour
+    /// after the main merge; 0 or 1 disables the pass.
+    pub min_region_size: u32,
+    /// Tile size used by the parallel engine.
+    pub tile_width: usize,
+    pub tile_height: usize,
+    /// Distance used as the weight of the edge between neighbouring pixels.
+    pub metric: Metric,
+    /// Per-band factors applied to the samples before the metric sees them, one
+    /// per channel of the source; `None` weighs every band equally.
+    pub band_weights: Option<Vec<f32>>,
+    /// Which neighbouring pixels are joined by an edge.
+    pub connectivity: Connectivity,
+    /// Region of interest. Pixels outside it get no edges and are labelled
+    /// `Segmentation::IGNORED`.
+    pub mask: Option<Mask>,
+    /// Handling of the alpha channel of sources that have one.
+    pub alpha: AlphaPolicy,
+    /// Must-link / cannot-link scribbles honoured by every merge phase.
+    pub scribbles: Option<Scribbles>,
+    /// Keep a snapshot of the partition after every merge phase.
+    pub snapshots: bool,
+    /// Record every union into a `MergeTree` that can be cut at any level later.
+    pub merge_tree: bool,
+    /// Merge in a fixed edge order so that the partition is the same on every
+    /// run, for any thread count or tile size, and identical between the serial
+    /// and parallel engines. The parallel engine then runs its credit phase
+    /// sequentially. Merge trees may still differ below the threshold.
+    pub deterministic: bool,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            engine: Engine::Parallel,
+            algorithm: Algorithm::default(),
+            threshold: 13f32,
+            threshold_units: ThresholdUnits::default(),
+            auto_threshold: None,
+            contrast: -3f32,
+            criterion: Criterion::default(),
+            target_regions: None,
+            min_region_size: 0,
+            tile_width: 64,
+            tile_height: 64,
+            metric: Metric::default(),
+            band_weights: None,
+            connectivity: Connectivity::default(),
+            mask: None,
+            alpha: AlphaPolicy::default(),
+            scribbles: None,
+            snapshots: false,
+            merge_tree: false,
+            deterministic: false,
+        }
+    }
+}
+
+impl Params {
+    pub fn merge_criterion(&self) -> Arc<dyn MergeCriterion> {
+        match self.algorithm {
+            Algorithm::Phmsf => self.criterion.build(self.contrast),
+            Algorithm::Felzenszwalb { k, .. } => Arc::new(Felzenszwalb { k }),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SegmentError> {
+        if self.threshold.is_nan() {
+            return Err(SegmentError::invalid("threshold", "must not be NaN"));
+        }
+        match self.auto_threshold {
+            Some(AutoThreshold::Percentile(percent)) if !(0f32..=100f32).contains(&percent) => {
+                return Err(SegmentError::invalid("percentile", "must be between 0 and 100"));
+            }
+            Some(AutoThreshold::Mad { factor }) if !factor.is_finite() => {
+                return Err(SegmentError::invalid("MAD factor", "must be a finite number"));
+            }
+            Some(_) if self.target_regions.is_some() => {
+                return Err(SegmentError::invalid("auto threshold", "cannot be combined with a target region count"));
+            }
+            _ => {}
+        }
+        if !self.contrast.is_finite() {
+            return Err(SegmentError::invalid("contrast", "must be a finite number"));
+        }
+        if let Algorithm::Felzenszwalb { k, .. } = self.algorithm {
+            if !(k.is_finite() && k >= 0f32) {
+                return Err(SegmentError::invalid("k", "must be a non-negative number"));
+            }
+        }
+        if let Some(target) = self.target_regions {
+            if target.count == 0 {
+                return Err(SegmentError::invalid("target region count", "must be at least 1"));
+            }
+            if self.algorithm != Algorithm::Phmsf {
+                return Err(SegmentError::invalid("target region count", "only supported by the phmsf algorithm"));
+            }
+        }
+        if self.tile_width == 0 || self.tile_height == 0 {
+            return Err(SegmentError::invalid("tile size", "must be greater than zero"));
+        }
+        if let Some(weights) = &self.band_weights {
+            if !weights.iter().all(|weight| weight.is_finite() && *weight >= 0f32) {
+                return Err(SegmentError::invalid("band weights", "must be non-negative numbers"));
+            }
+        }
+        if let AlphaPolicy::Channel(weight) = self.alpha {
+            if !(weight.is_finite() && weight >= 0f32) {
+                return Err(SegmentError::invalid("alpha weight", "must be a non-negative number"));
+            }
+        }
+        if !self.connectivity.has_neighbours() {
+            return Err(SegmentError::invalid("connectivity", "stencil has no neighbours"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::segmentation::Segmentation;
+use crate::source::ImageSource;
+use image::{Rgb, RgbImage};
+
+/// Paint every region with the mean colour of its pixels in `image`. Pixels
+/// outside the mask are painted black by both renderers.
+pub fn mean_colors<'a>(segmentation: &Segmentation, image: impl Into<ImageSource<'a>>) -> RgbImage {
+    let image = image.into();
+    let mut sums = vec![(0u64, 0u64, 0u64); segmentation.region_count];
+
+    for (i, &label) in segmentation.labels.iter().enumerate() {
+        if label == Segmentation::IGNORED {
+            continue;
+        }
+        let pixel = image.pixel((i % segmentation.width) as u32, (i / segmentation.width) as u32);
+        let sum = &mut sums[label as usize];
+        sum.0 += pixel[0] as u64;
+        sum.1 += pixel[1] as u64;
+        sum.2 += pixel[2] as u64;
+    }
+
+    let colors: Vec<Rgb<u8>> = sums
+        .iter()
+        .zip(&segmentation.region_sizes)
+        .map(|(sum, &count)| {
+            let count = count as u64;
+            Rgb([(sum.0 / count) as u8, (sum.1 / count) as u8, (sum.2 / count) as u8])
+        })
+        .collect();
+
+    paint(segmentation, |label| colors[label as usize])
+}
+
+/// Paint every region with a pseudo-random colour derived from its label, so that
+/// neighbouring regions stay distinguishable even when their mean colours match.
+pub fn label_colors(segmentation: &Segmentation) -> RgbImage {
+    paint(segmentation, |label| {
+        let hash = (label as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        Rgb([(hash >> 56) as u8, (hash >> 48) as u8, (hash >> 40) as u8])
+    })
+}
+
+fn paint(segmentation: &Segmentation, color: impl Fn(u32) -> Rgb<u8>) -> RgbImage {
+    RgbImage::from_fn(segmentation.width as u32, segmentation.height as u32, |x, y| {
+        match segmentation.label(x as usize, y as usize) {
+            Segmentation::IGNORED => Rgb([0, 0, 0]),
+            label => color(label),
+        }
+    })
+}
@@ -0,0 +1,81 @@
+use image::DynamicImage;
+
+/// Channel sample type an `ImageSource` can hold: `u8`, `u16` or `f32`.
+///
+/// Edge weights are computed from the samples at full `f32` precision, so 16-bit
+/// and floating-point sources keep their resolution.
+pub trait Sample: Copy + Send + Sync + 'static {
+    /// Full-scale value: white for colour channels, opaque for alpha.
+    const MAX: Self;
+
+    fn to_f32(self) -> f32;
+
+    /// Packed RGB samples of `image` when it already stores them in this type.
+    fn borrow_rgb(image: &DynamicImage) -> Option<&[Self]>;
+
+    /// `image` converted to packed RGB samples of this type.
+    fn to_rgb(image: &DynamicImage) -> Vec<Self>;
+
+    /// `image` converted to packed RGBA samples of this type.
+    fn to_rgba(image: &DynamicImage) -> Vec<Self>;
+}
+
+impl Sample for u8 {
+    const MAX: u8 = u8::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn borrow_rgb(image: &DynamicImage) -> Option<&[u8]> {
+        image.as_rgb8().map(|image| image.as_raw().as_slice())
+    }
+
+    fn to_rgb(image: &DynamicImage) -> Vec<u8> {
+        image.to_rgb8().into_raw()
+    }
+
+    fn to_rgba(image: &DynamicImage) -> Vec<u8> {
+        image.to_rgba8().into_raw()
+    }
+}
+
+impl Sample for u16 {
+    const MAX: u16 = u16::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn borrow_rgb(image: &DynamicImage) -> Option<&[u16]> {
+        image.as_rgb16().map(|image| image.as_raw().as_slice())
+    }
+
+    fn to_rgb(image: &DynamicImage) -> Vec<u16> {
+        image.to_rgb16().into_raw()
+    }
+
+    fn to_rgba(image: &DynamicImage) -> Vec<u16> {
+        image.to_rgba16().into_raw()
+    }
+}
+
+impl Sample for f32 {
+    const MAX: f32 = 1f32;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn borrow_rgb(image: &DynamicImage) -> Option<&[f32]> {
+        image.as_rgb32f().map(|image| image.as_raw().as_slice())
+    }
+
+    fn to_rgb(image: &DynamicImage) -> Vec<f32> {
+        image.to_rgb32f().into_raw()
+    }
+
+    fn to_rgba(image: &DynamicImage) -> Vec<f32> {
+        image.to_rgba32f().into_raw()
+    }
+}
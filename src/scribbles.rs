@@ -0,0 +1,50 @@
+use crate::error::SegmentError;
+use image::DynamicImage;
+
+/// User scribbles that constrain the merge loops.
+///
+/// Pixels under the same label must end up in the same region and pixels under
+/// different labels never share one. Both engines join every label's pixels
+/// before the first merge phase and refuse any union between two labels.
+#[derive(Debug, Clone, Default)]
+pub struct Scribbles {
+    seeds: Vec<(u32, u32, u32)>, // (x, y, label)
+}
+
+impl Scribbles {
+    pub fn new() -> Scribbles {
+        Scribbles::default()
+    }
+
+    /// Every non-zero luma value of `image` is read as the label of that pixel.
+    pub fn from_image(image: &DynamicImage) -> Scribbles {
+        let luma = image.to_luma8();
+        let seeds = luma.enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] != 0)
+            .map(|(x, y, pixel)| (x, y, pixel.0[0] as u32))
+            .collect();
+        Scribbles { seeds }
+    }
+
+    /// Mark `points` as `(x, y)` pixels of scribble `label`.
+    pub fn add(&mut self, label: u32, points: impl IntoIterator<Item = (u32, u32)>) {
+        self.seeds.extend(points.into_iter().map(|(x, y)| (x, y, label)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seeds.is_empty()
+    }
+
+    /// Seeds as `(node, label)` for an image of the given size.
+    pub(crate) fn nodes(&self, width: u32, height: u32) -> Result<Vec<(usize, u32)>, SegmentError> {
+        self.seeds.iter().map(|&(x, y, label)| {
+            if x >= width || y >= height {
+                return Err(SegmentError::invalid("scribbles", format!("pixel ({}, {}) lies outside the image", x, y)));
+            }
+            if label == u32::MAX {
+                return Err(SegmentError::invalid("scribbles", "label u32::MAX is reserved"));
+            }
+            Ok((y as usize * width as usize + x as usize, label))
+        }).collect()
+    }
+}
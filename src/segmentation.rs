@@ -0,0 +1,200 @@
+use crate::mask::Mask;
+use crate::sample::Sample;
+use crate::source::ImageSource;
+use crate::tree::MergeTree;
+
+/// Merge phases after which a snapshot can be taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Unconditional merge of edges below `threshold`.
+    Threshold,
+    /// Parallel engine: threshold merge across tile borders.
+    BorderMerge,
+    /// Parallel engine: credit merge of tile-local regions.
+    Heuristic,
+    /// Parallel engine: credit merge of the delayed, tile-spanning edges.
+    DelayQueue,
+    /// Serial engine: credit merge over the remaining edges.
+    Credit,
+    /// Felzenszwalb–Huttenlocher merge over all edges.
+    Felzenszwalb,
+    /// Absorption of components below the minimum size.
+    MinSize,
+}
+
+/// Partition as it stood right after `phase`.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub phase: Phase,
+    pub segmentation: Segmentation,
+}
+
+/// Where two partitions of the same image disagree, from `Segmentation::compare`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Pixels whose pair of labels contradicts the pairing first seen for
+    /// either label.
+    pub pixels: usize,
+    /// First such pixel as `(x, y)`, in row-major order.
+    pub first: (usize, usize),
+    /// Region counts of the two partitions.
+    pub regions: (usize, usize),
+}
+
+/// Result of a segmentation run.
+#[derive(Debug, Clone)]
+pub struct Segmentation {
+    pub width: usize,
+    pub height: usize,
+    /// Region label of every pixel, row-major. Labels are numbered `0..region_count`
+    /// in the order their regions are first met while scanning the image; pixels
+    /// outside the `Params::mask` get `Segmentation::IGNORED`.
+    pub labels: Vec<u32>,
+    pub region_count: usize,
+    /// Number of pixels in each region, indexed by label.
+    pub region_sizes: Vec<u32>,
+    /// Bands per pixel of the source, and the mean of every band over each
+    /// region in native units: `channels` values per label. Left empty on
+    /// snapshots and merge-tree cuts.
+    pub channels: usize,
+    pub region_means: Vec<f32>,
+    /// Threshold and contrast the run actually used; they differ from `Params`
+    /// when chosen automatically.
+    pub threshold: f32,
+    pub contrast: f32,
+    /// Intermediate partitions, filled only when `Params::snapshots` is set.
+    pub snapshots: Vec<Snapshot>,
+    /// Every union of the run, filled only when `Params::merge_tree` is set.
+    pub merge_tree: Option<MergeTree>,
+}
+
+impl Segmentation {
+    /// Label of the pixels outside the region of interest.
+    pub const IGNORED: u32 = u32::MAX;
+
+    /// Build a segmentation from a `find` that maps each pixel index to its region root.
+    pub(crate) fn from_roots(width: usize, height: usize, mut find: impl FnMut(usize) -> usize) -> Segmentation {
+        let nodes = width * height;
+        let mut root_label = vec![u32::MAX; nodes];
+        let mut labels = Vec::with_capacity(nodes);
+        let mut region_sizes: Vec<u32> = Vec::new();
+
+        for node in 0..nodes {
+            let root = find(node);
+            if root_label[root] == u32::MAX {
+                root_label[root] = region_sizes.len() as u32;
+                region_sizes.push(0);
+            }
+            let label = root_label[root];
+            region_sizes[label as usize] += 1;
+            labels.push(label);
+        }
+
+        Segmentation {
+            width,
+            height,
+            labels,
+            region_count: region_sizes.len(),
+            region_sizes,
+            channels: 0,
+            region_means: Vec::new(),
+            threshold: f32::NAN,
+            contrast: f32::NAN,
+            snapshots: Vec::new(),
+            merge_tree: None,
+        }
+    }
+
+    /// Compare the partition with `other`, ignoring how regions are numbered.
+    /// Returns `None` when both group the pixels identically. Segmentations of
+    /// different sizes mismatch on every pixel of the larger one.
+    pub fn compare(&self, other: &Segmentation) -> Option<Mismatch> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Some(Mismatch {
+                pixels: self.labels.len().max(other.labels.len()),
+                first: (0, 0),
+                regions: (self.region_count, other.region_count),
+            });
+        }
+        // IGNORED labels map to the slot past the last region.
+        let mut forward = vec![u32::MAX; self.region_count + 1];
+        let mut backward = vec![u32::MAX; other.region_count + 1];
+        let slot = |label: u32, count: usize| if label == Segmentation::IGNORED { count } else { label as usize };
+
+        let mut pixels = 0;
+        let mut first = None;
+        for (node, (&a, &b)) in self.labels.iter().zip(&other.labels).enumerate() {
+            let (i, j) = (slot(a, self.region_count), slot(b, other.region_count));
+            if forward[i] == u32::MAX && backward[j] == u32::MAX {
+                forward[i] = j as u32;
+                backward[j] = i as u32;
+            } else if forward[i] != j as u32 || backward[j] != i as u32 {
+                pixels += 1;
+                first.get_or_insert((node % self.width, node / self.width));
+            }
+        }
+
+        first.map(|first| Mismatch { pixels, first, regions: (self.region_count, other.region_count) })
+    }
+
+    pub fn label(&self, x: usize, y: usize) -> u32 {
+        self.labels[y * self.width + x]
+    }
+
+    /// Mean band values of the region `label`.
+    pub fn mean(&self, label: u32) -> &[f32] {
+        &self.region_means[label as usize * self.channels..(label as usize + 1) * self.channels]
+    }
+
+    /// Fill `region_means` from the samples of `image`.
+    pub(crate) fn compute_means<S: Sample>(&mut self, image: &ImageSource<S>) {
+        let channels = image.channels();
+        let mut sums = vec![0f64; self.region_count * channels];
+        for (i, &label) in self.labels.iter().enumerate() {
+            if label == Segmentation::IGNORED {
+                continue;
+            }
+            let samples = image.samples((i % self.width) as u32, (i / self.width) as u32);
+            for (sum, sample) in sums[label as usize * channels..].iter_mut().zip(samples) {
+                *sum += sample.to_f32() as f64;
+            }
+        }
+
+        self.channels = channels;
+        self.region_means = sums
+            .chunks(channels.max(1))
+            .zip(&self.region_sizes)
+            .flat_map(|(sums, &size)| sums.iter().map(move |sum| (sum / size as f64) as f32))
+            .collect();
+    }
+
+    /// Give every pixel outside `mask` the `IGNORED` label and renumber the rest,
+    /// snapshots included. Useful on cuts of a `MergeTree` built with a mask.
+    pub fn apply_mask(&mut self, mask: &Mask) {
+        let mut relabel = vec![u32::MAX; self.region_count];
+        let mut region_sizes: Vec<u32> = Vec::new();
+
+        for (node, label) in self.labels.iter_mut().enumerate() {
+            if *label == Segmentation::IGNORED || !mask.keeps(node) {
+                *label = Segmentation::IGNORED;
+                continue;
+            }
+            let old = *label as usize;
+            if relabel[old] == u32::MAX {
+                relabel[old] = region_sizes.len() as u32;
+                region_sizes.push(0);
+            }
+            *label = relabel[old];
+            region_sizes[*label as usize] += 1;
+        }
+
+        self.region_count = region_sizes.len();
+        self.region_sizes = region_sizes;
+        // Means no longer line up with the new labels.
+        self.channels = 0;
+        self.region_means.clear();
+        for snapshot in &mut self.snapshots {
+            snapshot.segmentation.apply_mask(mask);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::{check_dimensions, SegmentError};
+use crate::metric::WeightMetric;
+use crate::params::{AlphaPolicy, Params, ThresholdUnits};
+use crate::sample::Sample;
+use image::{DynamicImage, RgbImage, RgbaImage};
+use std::borrow::Cow;
+
+/// Interleaved samples that the graph builders read from: RGB for images, any
+/// number of bands for multispectral rasters, plus a separate alpha plane when
+/// the source has one. `S` is the sample type: `u8` (the default), `u16` or `f32`.
+///
+/// Borrows the caller's buffer whenever it is already packed RGB of type `S` and
+/// converts otherwise, so segmenting an in-memory frame never touches the filesystem.
+#[derive(Debug, Clone)]
+pub struct ImageSource<'a, S: Sample = u8> {
+    data: Cow<'a, [S]>,
+    width: u32,
+    height: u32,
+    stride: usize, // samples per row
+    channels: usize,
+    alpha: Option<Cow<'a, [S]>>, // one sample per pixel, row-major
+}
+
+impl<'a> ImageSource<'a> {
+    pub fn from_rgb_image(image: &'a RgbImage) -> ImageSource<'a> {
+        let (width, height) = image.dimensions();
+        ImageSource {
+            data: Cow::Borrowed(image.as_raw()),
+            width,
+            height,
+            stride: width as usize * 3,
+            channels: 3,
+            alpha: None,
+        }
+    }
+
+    /// Split RGBA8 pixels into the RGB data and the alpha plane.
+    pub fn from_rgba_image(image: &RgbaImage) -> ImageSource<'a> {
+        let (width, height) = image.dimensions();
+        ImageSource::from_rgba(image.as_raw(), width, height)
+    }
+}
+
+impl<'a, S: Sample> ImageSource<'a, S> {
+    /// Read `image` as samples of type `S`; a 16-bit image read as `u16` keeps
+    /// its full precision.
+    pub fn from_dynamic_image(image: &'a DynamicImage) -> ImageSource<'a, S> {
+        let (width, height) = (image.width(), image.height());
+        if let Some(data) = S::borrow_rgb(image) {
+            return ImageSource { data: Cow::Borrowed(data), width, height, stride: width as usize * 3, channels: 3, alpha: None };
+        }
+        if image.color().has_alpha() {
+            return ImageSource::from_rgba(&S::to_rgba(image), width, height);
+        }
+        ImageSource {
+            data: Cow::Owned(S::to_rgb(image)),
+            width,
+            height,
+            stride: width as usize * 3,
+            channels: 3,
+            alpha: None,
+        }
+    }
+
+    fn from_rgba(rgba: &[S], width: u32, height: u32) -> ImageSource<'a, S> {
+        let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+        let mut alpha = Vec::with_capacity(width as usize * height as usize);
+        for pixel in rgba.chunks_exact(4) {
+            data.extend_from_slice(&pixel[..3]);
+            alpha.push(pixel[3]);
+        }
+        ImageSource {
+            data: Cow::Owned(data),
+            width,
+            height,
+            stride: width as usize * 3,
+            channels: 3,
+            alpha: Some(Cow::Owned(alpha)),
+        }
+    }
+
+    /// Wrap rows of packed RGB pixels that start `stride` samples apart.
+    pub fn from_raw(data: &'a [S], width: u32, height: u32, stride: usize) -> Result<ImageSource<'a, S>, SegmentError> {
+        ImageSource::from_bands(data, width, height, 3, stride)
+    }
+
+    /// Wrap rows of pixels with `channels` interleaved bands each, such as a
+    /// multispectral raster, that start `stride` samples apart.
+    pub fn from_bands(data: &'a [S], width: u32, height: u32, channels: usize, stride: usize) -> Result<ImageSource<'a, S>, SegmentError> {
+        check_dimensions(width, height)?;
+        if channels == 0 {
+            return Err(SegmentError::invalid("channels", "must be at least 1"));
+        }
+        let row = width as usize * channels;
+        if stride < row {
+            return Err(SegmentError::invalid("stride", format!("{} is shorter than a row of {} samples", stride, row)));
+        }
+        let needed = if height == 0 { 0 } else { stride * (height as usize - 1) + row };
+        if data.len() < needed {
+            return Err(SegmentError::invalid("buffer", format!("{} samples given, {} needed", data.len(), needed)));
+        }
+        Ok(ImageSource {
+            data: Cow::Borrowed(data),
+            width,
+            height,
+            stride,
+            channels,
+            alpha: None,
+        })
+    }
+
+    /// Attach an alpha plane of one sample per pixel, row-major.
+    pub fn with_alpha(self, alpha: &'a [S]) -> Result<ImageSource<'a, S>, SegmentError> {
+        let needed = self.width as usize * self.height as usize;
+        if alpha.len() != needed {
+            return Err(SegmentError::invalid("alpha", format!("{} samples given, {} needed", alpha.len(), needed)));
+        }
+        Ok(ImageSource { alpha: Some(Cow::Borrowed(alpha)), ..self })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Every band of the pixel.
+    pub fn samples(&self, x: u32, y: u32) -> &[S] {
+        let i = y as usize * self.stride + x as usize * self.channels;
+        &self.data[i..i + self.channels]
+    }
+
+    /// Colour of the pixel for previews: its first three bands, with the last
+    /// band repeated when there are fewer.
+    pub fn pixel(&self, x: u32, y: u32) -> [S; 3] {
+        let samples = self.samples(x, y);
+        [0, 1, 2].map(|band| samples[band.min(self.channels - 1)])
+    }
+
+    /// All samples as `f32` in native units, row-major, `channels` per pixel.
+    pub(crate) fn values(&self) -> Vec<f32> {
+        let mut values = Vec::with_capacity(self.width as usize * self.height as usize * self.channels);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                values.extend(self.samples(x, y).iter().map(|sample| sample.to_f32()));
+            }
+        }
+        values
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    /// Alpha of the pixel; `S::MAX` when the source has no alpha channel.
+    pub fn alpha(&self, x: u32, y: u32) -> S {
+        match &self.alpha {
+            Some(alpha) => alpha[y as usize * self.width as usize + x as usize],
+            None => S::MAX,
+        }
+    }
+
+    /// Factor applied to the samples before they reach `metric`. Metrics with a
+    /// fixed range get samples rescaled to it; the others get native samples, or
+    /// samples divided by `S::MAX` under `ThresholdUnits::Normalized`.
+    fn scale(&self, metric: &dyn WeightMetric, params: &Params) -> f32 {
+        match (metric.range(), params.threshold_units) {
+            (Some(range), _) => range / S::MAX.to_f32(),
+            (None, ThresholdUnits::Native) => 1f32,
+            (None, ThresholdUnits::Normalized) => 1f32 / S::MAX.to_f32(),
+        }
+    }
+
+    /// Values handed to the `WeightMetric` for every pixel, row-major: the bands
+    /// rescaled by `scale` and `Params::band_weights`, followed by alpha scaled by
+    /// the weight of `AlphaPolicy::Channel` when the source has alpha.
+    /// Returns the values and how many there are per pixel.
+    pub(crate) fn features(&self, params: &Params) -> Result<(Vec<f32>, usize), SegmentError> {
+        if self.channels < params.metric.min_channels() {
+            return Err(SegmentError::invalid("metric", format!(
+                "needs {} channels but the source has {}", params.metric.min_channels(), self.channels
+            )));
+        }
+        let scale = self.scale(&params.metric, params);
+        let weights = match &params.band_weights {
+            Some(weights) if weights.len() != self.channels => {
+                return Err(SegmentError::invalid("band weights", format!(
+                    "{} given for {} channels", weights.len(), self.channels
+                )));
+            }
+            Some(weights) => weights.iter().map(|weight| weight * scale).collect(),
+            None => vec![scale; self.channels],
+        };
+        let alpha = match (params.alpha, &self.alpha) {
+            (AlphaPolicy::Channel(weight), Some(_)) => Some(weight * scale),
+            _ => None,
+        };
+
+        let dims = self.channels + alpha.is_some() as usize;
+        let mut features = Vec::with_capacity(self.width as usize * self.height as usize * dims);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                features.extend(self.samples(x, y).iter().zip(&weights).map(|(sample, weight)| sample.to_f32() * weight));
+                if let Some(weight) = alpha {
+                    features.push(self.alpha(x, y).to_f32() * weight);
+                }
+            }
+        }
+        Ok((features, dims))
+    }
+}
+
+impl<'a> From<&'a DynamicImage> for ImageSource<'a> {
+    fn from(image: &'a DynamicImage) -> Self {
+        ImageSource::from_dynamic_image(image)
+    }
+}
+
+impl<'a> From<&'a RgbImage> for ImageSource<'a> {
+    fn from(image: &'a RgbImage) -> Self {
+        ImageSource::from_rgb_image(image)
+    }
+}
+
+impl<'a, S: Sample> From<&'a ImageSource<'a, S>> for ImageSource<'a, S> {
+    fn from(source: &'a ImageSource<'a, S>) -> Self {
+        ImageSource {
+            data: Cow::Borrowed(&source.data),
+            alpha: source.alpha.as_deref().map(Cow::Borrowed),
+            ..*source
+        }
+    }
+}
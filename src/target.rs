@@ -0,0 +1,78 @@
+use crate::graph::kruskal::Kruskal;
+use crate::params::{Params, TargetRegions};
+
+// Contrast is searched in [-CONTRAST_RANGE, CONTRAST_RANGE].
+const CONTRAST_RANGE: f32 = 64f32;
+const CONTRAST_STEPS: usize = 24;
+
+/// Search the threshold (and optionally the contrast) whose PHMSF run on the
+/// serial engine comes closest to `target.count` regions.
+///
+/// Candidate thresholds are the distinct weights of `kruskal`'s sorted edges, so
+/// the graph is built and sorted only once. Returns `(threshold, contrast)`.
+pub(crate) fn search(kruskal: &mut Kruskal, params: &Params, target: &TargetRegions) -> (f32, f32) {
+    let mut candidates: Vec<f32> = vec![0f32];
+    for &(weight, _, _) in kruskal.edges() {
+        if candidates.last() != Some(&weight) {
+            candidates.push(weight);
+        }
+    }
+    // One past the heaviest edge merges everything in the threshold phase.
+    candidates.push(candidates[candidates.len() - 1] + 1f32);
+
+    // Only regions inside the mask count. Masked pixels are usually singletons,
+    // but scribbles may have joined some of them, so relabel rather than subtract.
+    let mut count = |threshold: f32, contrast: f32| {
+        kruskal.reset(threshold, params.criterion.build(contrast));
+        let mut segmentation = kruskal.run(params.min_region_size, false);
+        if let Some(mask) = &params.mask {
+            segmentation.apply_mask(mask);
+        }
+        segmentation.region_count
+    };
+
+    let mut best = (params.threshold, params.contrast, usize::MAX);
+    let consider = |best: &mut (f32, f32, usize), threshold: f32, contrast: f32, regions: usize| {
+        if regions.abs_diff(target.count) < best.2.abs_diff(target.count) {
+            *best = (threshold, contrast, regions);
+        }
+    };
+
+    // The region count falls as the threshold rises.
+    let (mut lo, mut hi) = (0, candidates.len() - 1);
+    while lo <= hi {
+        let mid = (lo + hi) / 2;
+        let regions = count(candidates[mid], params.contrast);
+        consider(&mut best, candidates[mid], params.contrast, regions);
+        if regions == target.count {
+            break;
+        } else if regions > target.count {
+            lo = mid + 1;
+        } else if mid == 0 {
+            break;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    // The region count rises with the contrast.
+    if target.search_contrast {
+        let threshold = best.0;
+        let (mut lo, mut hi) = (-CONTRAST_RANGE, CONTRAST_RANGE);
+        for _ in 0..CONTRAST_STEPS {
+            if best.2 == target.count {
+                break;
+            }
+            let mid = (lo + hi) / 2f32;
+            let regions = count(threshold, mid);
+            consider(&mut best, threshold, mid, regions);
+            if regions > target.count {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+    }
+
+    (best.0, best.1)
+}
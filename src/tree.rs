@@ -0,0 +1,217 @@
+use crate::error::SegmentError;
+use crate::segmentation::Segmentation;
+use std::io::{BufRead, Write};
+
+/// One internal node of the merge tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Merge {
+    /// Ids below `MergeTree::leaves()` are pixels; id `leaves() + i` is `merges()[i]`.
+    pub left: u32,
+    pub right: u32,
+    /// Weight of the edge whose union created this node. Not monotone up the
+    /// tree: later phases can merge across lighter edges than earlier ones.
+    pub weight: f32,
+    /// Number of pixels below this node.
+    pub size: u32,
+}
+
+/// Dendrogram of every union a segmentation run performed, in the order it
+/// performed them. Cutting it at a weight or region count gives a flat partition
+/// without re-running the segmentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeTree {
+    width: usize,
+    height: usize,
+    merges: Vec<Merge>,
+}
+
+impl MergeTree {
+    /// Build from `(kept root, absorbed root, weight)` unions in the order they happened.
+    pub(crate) fn from_unions(width: usize, height: usize, unions: &[(usize, usize, f32)]) -> MergeTree {
+        let leaves = width * height;
+        let mut node_of: Vec<u32> = (0..leaves as u32).collect();
+        let mut merges: Vec<Merge> = Vec::with_capacity(unions.len());
+
+        for &(u, v, weight) in unions {
+            let (left, right) = (node_of[u], node_of[v]);
+            let size_of = |node: u32, merges: &[Merge]| {
+                if (node as usize) < leaves { 1 } else { merges[node as usize - leaves].size }
+            };
+            let size = size_of(left, &merges) + size_of(right, &merges);
+            node_of[u] = (leaves + merges.len()) as u32;
+            merges.push(Merge { left, right, weight, size });
+        }
+
+        MergeTree { width, height, merges }
+    }
+
+    pub fn leaves(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn merges(&self) -> &[Merge] {
+        &self.merges
+    }
+
+    /// Height of every merge: the heaviest weight on the way down to its leaves.
+    /// Unlike the weights these never decrease towards the root, so every set of
+    /// merges up to some height holds the children of each of its merges.
+    pub fn heights(&self) -> Vec<f32> {
+        let leaves = self.leaves();
+        let mut heights: Vec<f32> = Vec::with_capacity(self.merges.len());
+        for merge in &self.merges {
+            let height = |node: u32, heights: &[f32]| {
+                if (node as usize) < leaves { f32::NEG_INFINITY } else { heights[node as usize - leaves] }
+            };
+            let height = merge.weight.max(height(merge.left, &heights)).max(height(merge.right, &heights));
+            heights.push(height);
+        }
+        heights
+    }
+
+    /// Partition made of every merge whose height is at most `weight`.
+    pub fn cut(&self, weight: f32) -> Segmentation {
+        let heights = self.heights();
+        self.partition(self.merges.iter().zip(&heights).filter(|(_, &height)| height <= weight).map(|(merge, _)| merge))
+    }
+
+    /// Partition with `count` regions (or as close as the tree allows), applying
+    /// merges from the lowest height up.
+    pub fn cut_regions(&self, count: usize) -> Segmentation {
+        let heights = self.heights();
+        let mut order: Vec<usize> = (0..self.merges.len()).collect();
+        // Stable, so a child tied with its parent still comes first.
+        order.sort_by(|&a, &b| heights[a].total_cmp(&heights[b]));
+        let keep = self.leaves().saturating_sub(count).min(order.len());
+        self.partition(order.into_iter().take(keep).map(|i| &self.merges[i]))
+    }
+
+    fn partition<'a>(&self, merges: impl Iterator<Item = &'a Merge>) -> Segmentation {
+        let leaves = self.leaves();
+
+        // Any pixel below a node stands in for the whole node.
+        let mut representative: Vec<u32> = Vec::with_capacity(self.merges.len());
+        for merge in &self.merges {
+            let left = merge.left as usize;
+            let rep = if left < leaves { merge.left } else { representative[left - leaves] };
+            representative.push(rep);
+        }
+        let rep = |node: u32| {
+            if (node as usize) < leaves { node as usize } else { representative[node as usize - leaves] as usize }
+        };
+
+        let mut parent: Vec<usize> = (0..leaves).collect();
+        fn find(parent: &mut [usize], mut node: usize) -> usize {
+            while parent[node] != node {
+                parent[node] = parent[parent[node]];
+                node = parent[node];
+            }
+            node
+        }
+
+        for merge in merges {
+            let u = find(&mut parent, rep(merge.left));
+            let v = find(&mut parent, rep(merge.right));
+            parent[v] = u;
+        }
+
+        Segmentation::from_roots(self.width, self.height, |node| find(&mut parent, node))
+    }
+
+    /// Write the tree as text: a `merge-tree 1` header, `width height merges`,
+    /// then one `left right weight size` line per merge.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(writer, "merge-tree 1")?;
+        writeln!(writer, "{} {} {}", self.width, self.height, self.merges.len())?;
+        for merge in &self.merges {
+            writeln!(writer, "{} {} {} {}", merge.left, merge.right, merge.weight, merge.size)?;
+        }
+        writer.flush()
+    }
+
+    /// Read a tree written by `write_to`.
+    pub fn read_from<R: BufRead>(reader: R) -> Result<MergeTree, SegmentError> {
+        let malformed = |reason: &str| SegmentError::invalid("merge tree", reason);
+        let mut lines = reader.lines();
+        let mut next_line = || -> Result<String, SegmentError> {
+            lines.next().ok_or_else(|| malformed("unexpected end of input"))?.map_err(SegmentError::Io)
+        };
+        fn fields<T: std::str::FromStr>(line: &str, count: usize) -> Option<Vec<T>> {
+            let values: Option<Vec<T>> = line.split_whitespace().map(|field| field.parse().ok()).collect();
+            values.filter(|values| values.len() == count)
+        }
+
+        if next_line()?.trim() != "merge-tree 1" {
+            return Err(malformed("missing header"));
+        }
+        let header: Vec<usize> = fields(&next_line()?, 3).ok_or_else(|| malformed("bad dimensions"))?;
+        let (width, height, count) = (header[0], header[1], header[2]);
+        let leaves = width
+            .checked_mul(height)
+            .filter(|&leaves| leaves <= u32::MAX as usize)
+            .ok_or_else(|| malformed("too many pixels"))?;
+        // A tree over `leaves` pixels has at most `leaves - 1` merges.
+        if count > leaves.saturating_sub(1) {
+            return Err(malformed("more merges than pixels"));
+        }
+
+        let mut merges = Vec::with_capacity(count);
+        for i in 0..count {
+            let line = next_line()?;
+            let mut parts = line.split_whitespace();
+            let (Some(left), Some(right), Some(weight), Some(size), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(malformed("bad merge line"));
+            };
+            let merge = match (left.parse(), right.parse(), weight.parse(), size.parse()) {
+                (Ok(left), Ok(right), Ok(weight), Ok(size)) => Merge { left, right, weight, size },
+                _ => return Err(malformed("bad merge line")),
+            };
+            // Children must exist before their parent.
+            if merge.left as usize >= leaves + i || merge.right as usize >= leaves + i {
+                return Err(malformed("merge refers to a later node"));
+            }
+            merges.push(merge);
+        }
+
+        Ok(MergeTree { width, height, merges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 image: the second merge is lighter than the first one below it.
+    fn tree() -> MergeTree {
+        MergeTree::from_unions(2, 2, &[(0, 1, 5f32), (0, 2, 3f32), (3, 0, 7f32)])
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let tree = tree();
+        let mut text = Vec::new();
+        tree.write_to(&mut text).unwrap();
+        assert_eq!(MergeTree::read_from(&text[..]).unwrap(), tree);
+    }
+
+    #[test]
+    fn cuts_on_monotone_heights() {
+        let tree = tree();
+        assert_eq!(tree.heights(), vec![5f32, 5f32, 7f32]);
+        // Below 5 nothing applies, not even the weight-3 merge above a weight-5 child.
+        assert_eq!(tree.cut(4f32).region_count, 4);
+        assert_eq!(tree.cut(5f32).region_count, 2);
+        assert_eq!(tree.cut_regions(3).region_count, 3);
+        assert_eq!(tree.cut_regions(3).labels, vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn rejects_oversized_headers() {
+        for header in ["18446744073709551615 2 0", "65536 65536 0", "2 2 4"] {
+            let text = format!("merge-tree 1\n{}\n", header);
+            assert!(MergeTree::read_from(text.as_bytes()).is_err());
+        }
+    }
+}